@@ -1,34 +1,54 @@
 use holodeck::cfg::cfg;
-use holodeck::gatekeeper::{Gatekeeper, GatekeeperBuilder};
+use holodeck::gatekeeper::{Episode, Gatekeeper, GatekeeperBuilder};
 use holodeck::logic::syntax::Prop;
-use holodeck::traffic::intersection::IntersectionBuilder;
-use holodeck::traffic::simulation::{Random as RandomController, SimulationBuilder};
+use holodeck::traffic::intersection::{Intersection, IntersectionBuilder};
+use holodeck::traffic::light::Phase;
+use holodeck::traffic::simulation::{Random as RandomController, Simulation, SimulationBuilder};
 use holodeck::traffic::trajectory::TrajectoryEntry;
 
-fn main() {
-    let n: u32 = cfg().get("max_timestamp").unwrap();
-    let intersection = IntersectionBuilder::new().build();
-    let simulation = SimulationBuilder::<RandomController>::new()
-        .with_intersection(intersection.clone())
-        .with_max_cars(16)
-        .with_drive_steps_per_lightswitch(8)
-        .with_max_steps(n)
-        .build();
-    let world = SimulationBuilder::<RandomController>::new()
+fn simulation(intersection: Intersection, n: u32) -> Simulation<RandomController> {
+    SimulationBuilder::<RandomController>::new()
         .with_intersection(intersection)
         .with_max_cars(16)
         .with_drive_steps_per_lightswitch(8)
         .with_max_steps(n)
-        .build();
-    let controller = RandomController::default();
-    fn traffic_safety(v: Vec<TrajectoryEntry>) -> Prop<TrajectoryEntry> {
-        Prop::Var(v).always()
-    }
+        .build()
+}
+
+fn traffic_safety(v: Vec<TrajectoryEntry>) -> Prop<TrajectoryEntry> {
+    Prop::Var(v).always()
+}
+
+fn gatekeeper(n: u32, seed: u64) -> Gatekeeper<RandomController, Simulation<RandomController>> {
+    let intersection = IntersectionBuilder::new().build();
+    GatekeeperBuilder::new(
+        simulation(intersection.clone(), n),
+        simulation(intersection, n),
+    )
+    .with_controller(RandomController::default())
+    .with_spec(traffic_safety)
+    .with_seed(seed)
+    .build()
+}
+
+fn report(name: &str, episode: &Episode<Phase, TrajectoryEntry>) {
+    println!(
+        "{}: {} crashes, {} throughput, {} interventions over {} periods",
+        name,
+        episode.num_crashes(),
+        episode.total_throughput(),
+        episode.num_interventions(),
+        episode.decisions().len()
+    );
+}
+
+fn main() {
+    let n: u32 = cfg().get("max_timestamp").unwrap();
+    let num_periods: u32 = cfg().get("num_periods").unwrap();
+    let seed: u64 = cfg().get("seed").unwrap();
     let _baseline = vec![TrajectoryEntry::new(0, 0); n as usize + 1];
-    let mut gatekeeper: Gatekeeper<RandomController, TrajectoryEntry> =
-        GatekeeperBuilder::new(simulation, world)
-            .with_controller(controller)
-            .with_spec(traffic_safety)
-            .build();
-    gatekeeper.run();
+    let gatekept = gatekeeper(n, seed).run_episode(num_periods);
+    let ungatekept = gatekeeper(n, seed).run_episode_ungatekept(num_periods);
+    report("gatekept", &gatekept);
+    report("ungatekept", &ungatekept);
 }
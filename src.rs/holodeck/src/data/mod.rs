@@ -1,2 +1,3 @@
 pub mod nodup_stack;
+pub mod pairs;
 pub mod prng;
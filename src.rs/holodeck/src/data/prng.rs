@@ -1,10 +1,23 @@
-use rand::SeedableRng;
+//! The one source of randomness in the simulator, so that a run is fully determined by its seed.
+use rand::{RngCore, SeedableRng};
+use rand_chacha::ChaCha12Rng;
+use serde::{Deserialize, Serialize};
 
-const N: usize = 64;
-#[derive(Clone, Debug)]
+const N: usize = 32;
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub struct RngSeed(pub [u8; N]);
+/// Where a generator is in its stream, so a run can be picked up exactly where it left off.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RngState {
+    seed: [u8; N],
+    stream: u64,
+    word_pos: u128,
+}
 #[derive(Clone, Debug)]
-pub struct Rng(RngSeed);
+pub struct Rng {
+    seed: RngSeed,
+    chacha: ChaCha12Rng,
+}
 
 impl RngSeed {
     pub fn new() -> RngSeed {
@@ -26,9 +39,30 @@ impl AsMut<[u8]> for RngSeed {
     }
 }
 
+impl Rng {
+    /// The seed this generator started from, for replaying a run.
+    pub fn seed(&self) -> &RngSeed {
+        &self.seed
+    }
+    pub fn state(&self) -> RngState {
+        RngState {
+            seed: self.chacha.get_seed(),
+            stream: self.chacha.get_stream(),
+            word_pos: self.chacha.get_word_pos(),
+        }
+    }
+    /// A generator that will draw the same numbers as the one `state` was taken from.
+    pub fn from_state(state: &RngState) -> Rng {
+        let mut rng = Rng::from_seed(RngSeed(state.seed));
+        rng.chacha.set_stream(state.stream);
+        rng.chacha.set_word_pos(state.word_pos);
+        rng
+    }
+}
+
 impl Default for Rng {
     fn default() -> Rng {
-        Rng(RngSeed::new())
+        Rng::from_seed(RngSeed::new())
     }
 }
 
@@ -36,6 +70,59 @@ impl SeedableRng for Rng {
     type Seed = RngSeed;
 
     fn from_seed(seed: RngSeed) -> Rng {
-        Rng(seed)
+        Rng {
+            chacha: ChaCha12Rng::from_seed(seed.0),
+            seed,
+        }
+    }
+}
+
+impl RngCore for Rng {
+    fn next_u32(&mut self) -> u32 {
+        self.chacha.next_u32()
+    }
+    fn next_u64(&mut self) -> u64 {
+        self.chacha.next_u64()
+    }
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.chacha.fill_bytes(dest)
+    }
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        self.chacha.try_fill_bytes(dest)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::Rng as _;
+
+    #[test]
+    fn same_seed_same_stream() {
+        let mut a = Rng::seed_from_u64(7);
+        let mut b = Rng::seed_from_u64(7);
+        let xs: Vec<u32> = (0..16).map(|_| a.gen()).collect();
+        let ys: Vec<u32> = (0..16).map(|_| b.gen()).collect();
+        assert_eq!(xs, ys);
+        assert_eq!(a.seed(), b.seed());
+    }
+
+    #[test]
+    fn different_seed_different_stream() {
+        let mut a = Rng::seed_from_u64(7);
+        let mut b = Rng::seed_from_u64(8);
+        let xs: Vec<u64> = (0..4).map(|_| a.gen()).collect();
+        let ys: Vec<u64> = (0..4).map(|_| b.gen()).collect();
+        assert_ne!(xs, ys);
+    }
+
+    #[test]
+    fn restored_state_continues_the_stream() {
+        let mut a = Rng::seed_from_u64(7);
+        let _: u32 = a.gen();
+        let mut b = Rng::from_state(&a.state());
+        let xs: Vec<u64> = (0..4).map(|_| a.gen()).collect();
+        let ys: Vec<u64> = (0..4).map(|_| b.gen()).collect();
+        assert_eq!(xs, ys);
     }
 }
@@ -0,0 +1,306 @@
+//! A gymnasium-style view of any `Model`: reset it, step it one decision period at a time and
+//! get back an observation, a reward and whether the episode is over.
+use crate::data::prng::Rng;
+use crate::gatekeeper::model::Model;
+use rand::SeedableRng;
+use std::rc::Rc;
+
+/// Maps what happened in one decision period to a reward, higher is better.
+pub type Reward<T> = Rc<dyn Fn(&T) -> f64>;
+
+/// Whether what happened in one decision period ends the episode.
+pub type Termination<T> = Rc<dyn Fn(&T) -> bool>;
+
+/// `(observation, reward, terminated, truncated, info)`, as in gymnasium.
+pub type StepResult<O, T> = (O, f64, bool, bool, Info<O, T>);
+
+static MAX_STEPS: u32 = 16;
+
+/// Everything about a step that isn't the observation or the reward.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Info<O, T> {
+    atom: T,
+    num_steps: u32,
+    final_observation: Option<O>,
+}
+
+impl<O, T> Info<O, T> {
+    /// What the decision period looked like to the spec.
+    pub fn atom(&self) -> &T {
+        &self.atom
+    }
+    /// Steps taken in the episode, including this one.
+    pub fn num_steps(&self) -> u32 {
+        self.num_steps
+    }
+    /// Only set by `VecEnv`, which resets a finished environment before returning: the last
+    /// observation of the episode that just ended.
+    pub fn final_observation(&self) -> Option<&O> {
+        self.final_observation.as_ref()
+    }
+}
+
+#[derive(Clone)]
+pub struct Env<M: Model> {
+    model: M,
+    initial: M::State,
+    reward: Reward<M::Atom>,
+    termination: Termination<M::Atom>,
+    max_steps: u32,
+    num_steps: u32,
+    /// What `rng` was last seeded with.
+    seed: u64,
+    rng: Rng,
+}
+
+pub struct EnvBuilder<M: Model> {
+    env: Env<M>,
+}
+
+impl<M: Model> EnvBuilder<M> {
+    /// Every episode starts from the state `model` is in now.
+    pub fn new(model: M) -> Self {
+        EnvBuilder {
+            env: Env {
+                initial: model.snapshot(),
+                model,
+                reward: Rc::new(|_| 0.0),
+                termination: Rc::new(|_| false),
+                max_steps: MAX_STEPS,
+                num_steps: 0,
+                seed: 0,
+                rng: Rng::default(),
+            },
+        }
+    }
+    pub fn with_reward<F>(mut self, reward: F) -> Self
+    where
+        F: Fn(&M::Atom) -> f64 + 'static,
+    {
+        self.env.reward = Rc::new(reward);
+        self
+    }
+    pub fn with_termination<F>(mut self, termination: F) -> Self
+    where
+        F: Fn(&M::Atom) -> bool + 'static,
+    {
+        self.env.termination = Rc::new(termination);
+        self
+    }
+    /// Episodes are truncated after this many steps.
+    pub fn with_max_steps(mut self, max_steps: u32) -> Self {
+        self.env.max_steps = max_steps;
+        self
+    }
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.env.seed = seed;
+        self.env.rng = Rng::seed_from_u64(seed);
+        self
+    }
+    pub fn build(self) -> Env<M> {
+        self.env
+    }
+}
+
+impl<M: Model> Env<M> {
+    pub fn model(&self) -> &M {
+        &self.model
+    }
+    pub fn max_steps(&self) -> u32 {
+        self.max_steps
+    }
+    pub fn num_steps(&self) -> u32 {
+        self.num_steps
+    }
+
+    /// Put the model back in its initial state. With a seed the random stream restarts from it,
+    /// without one it carries on from the previous episode.
+    pub fn reset(&mut self, seed: Option<u64>) -> M::Observation {
+        if let Some(seed) = seed {
+            self.seed = seed;
+            self.rng = Rng::seed_from_u64(seed);
+        }
+        self.model.sync_from(&self.initial);
+        self.num_steps = 0;
+        self.model.observe()
+    }
+
+    /// Apply `action` for one decision period.
+    pub fn step(&mut self, action: M::Action) -> StepResult<M::Observation, M::Atom> {
+        let atom = self.model.step(action, &mut self.rng);
+        self.num_steps += 1;
+        let reward = (self.reward)(&atom);
+        let terminated = (self.termination)(&atom);
+        let truncated = !terminated && self.num_steps >= self.max_steps;
+        let info = Info {
+            atom,
+            num_steps: self.num_steps,
+            final_observation: None,
+        };
+        (self.model.observe(), reward, terminated, truncated, info)
+    }
+}
+
+/// Many environments stepped together. An environment whose episode ends is reset on the spot,
+/// so the observation returned for it is the first of its next episode.
+#[derive(Clone)]
+pub struct VecEnv<M: Model> {
+    envs: Vec<Env<M>>,
+}
+
+impl<M: Model> VecEnv<M> {
+    pub fn new(envs: Vec<Env<M>>) -> Self {
+        VecEnv { envs }
+    }
+    /// `num_envs` copies of `env`, copy `i` seeded with `env`'s seed plus `i`, as in `reset`.
+    pub fn from_env(env: Env<M>, num_envs: usize) -> Self {
+        let envs = (0..num_envs)
+            .map(|i| {
+                let mut env = env.clone();
+                env.seed = env.seed.wrapping_add(i as u64);
+                env.rng = Rng::seed_from_u64(env.seed);
+                env
+            })
+            .collect();
+        VecEnv::new(envs)
+    }
+    pub fn num_envs(&self) -> usize {
+        self.envs.len()
+    }
+    pub fn envs(&self) -> &[Env<M>] {
+        &self.envs
+    }
+
+    /// Reset every environment. With a seed, environment `i` is seeded with `seed + i` so that
+    /// the copies don't all see the same traffic.
+    pub fn reset(&mut self, seed: Option<u64>) -> Vec<M::Observation> {
+        self.envs
+            .iter_mut()
+            .enumerate()
+            .map(|(i, env)| env.reset(seed.map(|seed| seed.wrapping_add(i as u64))))
+            .collect()
+    }
+
+    /// Step environment `i` with `actions[i]`.
+    pub fn step(&mut self, actions: Vec<M::Action>) -> Vec<StepResult<M::Observation, M::Atom>> {
+        assert_eq!(
+            actions.len(),
+            self.envs.len(),
+            "need one action per environment"
+        );
+        self.envs
+            .iter_mut()
+            .zip(actions)
+            .map(|(env, action)| {
+                let (observation, reward, terminated, truncated, mut info) = env.step(action);
+                if terminated || truncated {
+                    info.final_observation = Some(observation);
+                    let observation = env.reset(None);
+                    (observation, reward, terminated, truncated, info)
+                } else {
+                    (observation, reward, terminated, truncated, info)
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gatekeeper::tests::small_simulation;
+    use crate::traffic::light::Phase;
+    use crate::traffic::simulation::{Random, Simulation};
+    use crate::traffic::trajectory::{reward, TrajectoryEntry};
+
+    fn traffic_env() -> Env<Simulation<Random>> {
+        EnvBuilder::new(small_simulation())
+            .with_reward(reward(10.0))
+            .with_max_steps(3)
+            .build()
+    }
+
+    #[test]
+    fn step_truncates_at_max_steps() {
+        let mut env = traffic_env();
+        env.reset(Some(0));
+        let truncated: Vec<bool> = (0..3).map(|_| env.step(Phase::EastWest).3).collect();
+        assert_eq!(truncated, vec![false, false, true]);
+    }
+
+    #[test]
+    fn reward_is_throughput_minus_crash_penalty() {
+        let mut env = traffic_env();
+        env.reset(Some(3));
+        for _ in 0..3 {
+            let (_, reward, _, _, info) = env.step(Phase::NorthSouth);
+            let entry: &TrajectoryEntry = info.atom();
+            assert_eq!(
+                reward,
+                entry.num_cars_throughput() as f64 - 10.0 * entry.num_crashes_local() as f64
+            );
+        }
+    }
+
+    #[test]
+    fn reset_with_same_seed_replays_episode() {
+        let mut env = traffic_env();
+        let mut episode = |seed| {
+            env.reset(Some(seed));
+            (0..3)
+                .map(|_| env.step(Phase::NorthSouth).4.atom().clone())
+                .collect::<Vec<_>>()
+        };
+        let first = episode(7);
+        assert_eq!(first, episode(7));
+    }
+
+    #[test]
+    fn terminated_on_first_crash() {
+        let mut env = EnvBuilder::new(traffic_env().model().clone())
+            .with_termination(|entry: &TrajectoryEntry| entry.num_crashes_local() > 0)
+            .with_max_steps(64)
+            .build();
+        env.reset(Some(0));
+        // Switching every period with no all-red clearance eventually crashes.
+        let crashed = Phase::all().into_iter().cycle().take(64).find_map(|phase| {
+            let (_, _, terminated, _, info) = env.step(phase);
+            terminated.then(|| info.atom().num_crashes_local())
+        });
+        assert!(crashed.is_some_and(|num_crashes| num_crashes > 0));
+    }
+
+    #[test]
+    fn vec_env_copies_see_different_traffic() {
+        let env = EnvBuilder::new(traffic_env().model().clone())
+            .with_seed(5)
+            .build();
+        let mut envs = VecEnv::from_env(env, 4);
+        let mut spawned = [0; 4];
+        for _ in 0..3 {
+            for (num_spawned, result) in spawned
+                .iter_mut()
+                .zip(envs.step(vec![Phase::NorthSouth; 4]))
+            {
+                *num_spawned += result.4.atom().num_spawned_local();
+            }
+        }
+        assert!(spawned.iter().any(|&num_spawned| num_spawned != spawned[0]));
+    }
+
+    #[test]
+    fn vec_env_resets_finished_envs() {
+        let mut envs = VecEnv::from_env(traffic_env(), 4);
+        let observations = envs.reset(Some(0));
+        assert_eq!(observations.len(), 4);
+        for _ in 0..3 {
+            envs.step(vec![Phase::NorthSouth; 4]);
+        }
+        assert!(envs.envs().iter().all(|env| env.num_steps() == 0));
+        let results = envs.step(vec![Phase::NorthSouth; 4]);
+        assert!(results.iter().all(|result| result.4.num_steps() == 1));
+        assert!(results
+            .iter()
+            .all(|result| result.4.final_observation().is_none()));
+    }
+}
@@ -0,0 +1,172 @@
+//! How well the simulation predicts the world.
+//!
+//! The gatekeeper's guarantees are only as good as the simulation it certifies in, so every time
+//! an action runs in both we compare what was predicted with what was realized.
+use crate::logic::types::Valuation;
+
+static MAX_DISAGREEMENT: f64 = 0.1;
+static MIN_SAMPLES: u32 = 8;
+
+/// One bin of a calibration curve: of the verdicts the simulation valued in `[lower, upper)`,
+/// how often was the world actually safe?
+#[derive(Clone, Debug, PartialEq)]
+pub struct CalibrationBin {
+    pub lower: Valuation,
+    pub upper: Valuation,
+    pub count: u32,
+    pub mean_predicted: Valuation,
+    pub observed_safe: f64,
+}
+
+#[derive(Clone, Debug)]
+pub struct Fidelity {
+    /// Sum of absolute errors, per channel.
+    channel_errors: Vec<f64>,
+    num_steps: u32,
+    /// (simulated, realized) valuations of the spec.
+    verdicts: Vec<(Valuation, Valuation)>,
+    threshold: Valuation,
+    max_disagreement: f64,
+    min_samples: u32,
+}
+
+impl Default for Fidelity {
+    fn default() -> Self {
+        Fidelity::new(MAX_DISAGREEMENT, MIN_SAMPLES)
+    }
+}
+
+impl Fidelity {
+    /// The simulation is flagged as no longer predictive once at least `min_samples` verdicts
+    /// have been recorded and more than `max_disagreement` of them disagree with the world.
+    pub fn new(max_disagreement: f64, min_samples: u32) -> Self {
+        Fidelity {
+            channel_errors: Vec::new(),
+            num_steps: 0,
+            verdicts: Vec::new(),
+            threshold: 1.0 - crate::gatekeeper::EPSILON,
+            max_disagreement,
+            min_samples,
+        }
+    }
+
+    /// Compare one predicted decision period against the realized one, channel by channel.
+    pub fn record_step(&mut self, predicted: &[f64], realized: &[f64]) {
+        if self.channel_errors.len() < predicted.len() {
+            self.channel_errors.resize(predicted.len(), 0.0);
+        }
+        for (i, (p, r)) in predicted.iter().zip(realized).enumerate() {
+            self.channel_errors[i] += (p - r).abs();
+        }
+        self.num_steps += 1;
+    }
+
+    /// Compare the simulation's valuation of the spec against the world's.
+    pub fn record_verdict(&mut self, ofsim: Valuation, ofworld: Valuation) {
+        self.verdicts.push((ofsim, ofworld));
+    }
+
+    pub fn num_steps(&self) -> u32 {
+        self.num_steps
+    }
+
+    pub fn num_verdicts(&self) -> usize {
+        self.verdicts.len()
+    }
+
+    /// Mean absolute error per channel over every recorded step.
+    pub fn channel_errors(&self) -> Vec<f64> {
+        self.channel_errors
+            .iter()
+            .map(|error| error / self.num_steps.max(1) as f64)
+            .collect()
+    }
+
+    /// Fraction of verdicts where the simulation called it safe and the world didn't, or the
+    /// other way around.
+    pub fn disagreement_rate(&self) -> f64 {
+        let num_disagreements = self
+            .verdicts
+            .iter()
+            .filter(|(ofsim, ofworld)| (*ofsim > self.threshold) != (*ofworld > self.threshold))
+            .count();
+        num_disagreements as f64 / self.verdicts.len().max(1) as f64
+    }
+
+    /// Bucket the verdicts into `num_bins` equal-width bins of simulated valuation on `[0, 1]`.
+    pub fn calibration(&self, num_bins: usize) -> Vec<CalibrationBin> {
+        (0..num_bins)
+            .map(|i| {
+                let lower = i as f64 / num_bins as f64;
+                let upper = (i + 1) as f64 / num_bins as f64;
+                let in_bin: Vec<&(Valuation, Valuation)> = self
+                    .verdicts
+                    .iter()
+                    .filter(|(ofsim, _)| {
+                        lower <= *ofsim
+                            && (*ofsim < upper || (i + 1 == num_bins && *ofsim <= upper))
+                    })
+                    .collect();
+                let count = in_bin.len();
+                CalibrationBin {
+                    lower,
+                    upper,
+                    count: count as u32,
+                    mean_predicted: in_bin.iter().map(|(ofsim, _)| ofsim).sum::<f64>()
+                        / count.max(1) as f64,
+                    observed_safe: in_bin
+                        .iter()
+                        .filter(|(_, ofworld)| *ofworld > self.threshold)
+                        .count() as f64
+                        / count.max(1) as f64,
+                }
+            })
+            .collect()
+    }
+
+    /// False once the simulation disagrees with the world too often to be trusted.
+    pub fn is_predictive(&self) -> bool {
+        (self.verdicts.len() as u32) < self.min_samples
+            || self.disagreement_rate() <= self.max_disagreement
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn channel_errors_are_mean_absolute() {
+        let mut fidelity = Fidelity::default();
+        fidelity.record_step(&[1.0, 2.0], &[0.0, 2.0]);
+        fidelity.record_step(&[0.0, 5.0], &[0.0, 2.0]);
+        assert_eq!(fidelity.channel_errors(), vec![0.5, 1.5]);
+    }
+
+    #[test]
+    fn disagreement_flags_unpredictive_simulation() {
+        let mut fidelity = Fidelity::new(0.25, 4);
+        fidelity.record_verdict(1.0, 0.0);
+        fidelity.record_verdict(1.0, 0.0);
+        assert!(fidelity.is_predictive(), "too few samples to judge");
+        fidelity.record_verdict(1.0, 1.0);
+        fidelity.record_verdict(0.0, 0.0);
+        assert_eq!(fidelity.disagreement_rate(), 0.5);
+        assert!(!fidelity.is_predictive());
+    }
+
+    #[test]
+    fn calibration_bins_cover_unit_interval() {
+        let mut fidelity = Fidelity::default();
+        fidelity.record_verdict(0.1, 0.0);
+        fidelity.record_verdict(1.0, 1.0);
+        fidelity.record_verdict(1.0, 0.0);
+        let bins = fidelity.calibration(2);
+        assert_eq!(bins.len(), 2);
+        assert_eq!(bins[0].count, 1);
+        assert_eq!(bins[0].observed_safe, 0.0);
+        assert_eq!(bins[1].count, 2);
+        assert_eq!(bins[1].mean_predicted, 1.0);
+        assert_eq!(bins[1].observed_safe, 0.5);
+    }
+}
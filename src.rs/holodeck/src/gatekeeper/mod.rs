@@ -0,0 +1,806 @@
+//! The gatekeeper: certify a controller's proposals against a spec by rolling them out in a
+//! simulation before they reach the world.
+//!
+//! The certification loop only knows about the traits in `model`. The traffic intersection in
+//! `crate::traffic` is one implementation of them.
+//!
+//! We're going to /evaluate/ trajectories from the simulation with the LTL compiler.
+//! TODO we have this new idea where "world" and "sim" do not have a granularity difference. think about this more.
+//!     - instead, the atomic propositions will be `Trajectory`.
+//!     - or maybe the terms should just be some hashable thing that can map to trajectory, or trajectories.
+pub mod fidelity;
+pub mod model;
+pub mod shield;
+
+use crate::cfg::cfg;
+use crate::data::prng::Rng;
+use crate::gatekeeper::fidelity::Fidelity;
+use crate::gatekeeper::model::{Controller, FiniteActions, Model};
+use crate::logic::interpreter::interpret;
+use crate::logic::syntax::Prop;
+use crate::logic::types::{Atomic, Valuation};
+use rand::SeedableRng;
+use std::cmp::Ordering;
+
+/// Maps a trajectory of atoms to the proposition it has to satisfy.
+pub type Spec<T> = Box<dyn Fn(Vec<T>) -> Prop<T>>;
+
+/// Scores a trajectory by how well the system performed, higher is better. Unlike the spec it is
+/// only used to rank actions, never to certify them.
+pub type Performance<T> = Box<dyn Fn(&[T]) -> f64>;
+
+/// `W` is the world the certified actions are applied to. It defaults to the simulation's own type,
+/// but can be a different model of the same system as long as it snapshots to the same state.
+pub struct Gatekeeper<C, M, W = M>
+where
+    C: Controller<Observation = M::Observation, Action = M::Action>,
+    M: Model,
+    W: Model<State = M::State, Action = M::Action, Observation = M::Observation, Atom = M::Atom>,
+{
+    controller: C,
+    simulation: M,
+    world: W,
+    spec: Spec<M::Atom>,
+    performance: Performance<M::Atom>,
+    fallback: M::Action,
+    max_rejections: u32,
+    fidelity: Fidelity,
+    rng: Rng,
+}
+
+impl<C, M, W> Gatekeeper<C, M, W>
+where
+    C: Controller<Observation = M::Observation, Action = M::Action>,
+    M: Model,
+    W: Model<State = M::State, Action = M::Action, Observation = M::Observation, Atom = M::Atom>,
+{
+    // pub(crate) fn spec<F>(&self) -> Box<F>
+    // where
+    //     F: Fn(Vec<T>) -> Prop<T>,
+    // {
+    //     self.spec
+    // }
+    // missing getters cuz borrow checker and lifetimes.
+    pub(crate) fn spec_at(&self, atom: Vec<M::Atom>) -> Prop<M::Atom> {
+        (self.spec)(atom)
+    }
+    pub fn controller(&self) -> &C {
+        &self.controller
+    }
+    /// How well the simulation has predicted the world so far.
+    pub fn fidelity(&self) -> &Fidelity {
+        &self.fidelity
+    }
+    fn record_fidelity(
+        &mut self,
+        ofsim: &[M::Atom],
+        ofworld: &[M::Atom],
+        verdict: Option<(Valuation, Valuation)>,
+    ) {
+        for (predicted, realized) in ofsim.iter().zip(ofworld) {
+            self.fidelity
+                .record_step(&M::channels(predicted), &M::channels(realized));
+        }
+        if let Some((proba_safe_ofsim, proba_safe_ofworld)) = verdict {
+            let was_predictive = self.fidelity.is_predictive();
+            self.fidelity
+                .record_verdict(proba_safe_ofsim, proba_safe_ofworld);
+            if cfg().get("debug").unwrap() && was_predictive && !self.fidelity.is_predictive() {
+                println!(
+                    "Warning: the simulation is no longer predictive of the world, disagreeing on {} of verdicts",
+                    self.fidelity.disagreement_rate()
+                );
+            }
+        }
+    }
+}
+
+pub struct GatekeeperBuilder<C, M, W = M>
+where
+    C: Controller<Observation = M::Observation, Action = M::Action>,
+    M: Model,
+    W: Model<State = M::State, Action = M::Action, Observation = M::Observation, Atom = M::Atom>,
+{
+    gatekeeper: Gatekeeper<C, M, W>,
+}
+
+impl<C, M, W> GatekeeperBuilder<C, M, W>
+where
+    C: Controller<Observation = M::Observation, Action = M::Action>,
+    M: Model,
+    W: Model<State = M::State, Action = M::Action, Observation = M::Observation, Atom = M::Atom>,
+{
+    pub fn new(simulation: M, world: W) -> Self {
+        GatekeeperBuilder {
+            gatekeeper: Gatekeeper {
+                controller: C::default(),
+                simulation,
+                world,
+                spec: Box::new(|_| Prop::True),
+                performance: Box::new(|_| 0.0),
+                fallback: M::Action::default(),
+                max_rejections: MAX_REJECTIONS,
+                fidelity: Fidelity::default(),
+                rng: Rng::default(),
+            },
+        }
+    }
+    pub fn with_controller(mut self, controller: C) -> Self {
+        self.gatekeeper.controller = controller;
+        self
+    }
+    pub fn with_simulation(mut self, simulation: M) -> Self {
+        self.gatekeeper.simulation = simulation;
+        self
+    }
+    pub fn with_world(mut self, world: W) -> Self {
+        self.gatekeeper.world = world;
+        self
+    }
+    pub fn with_spec<F>(mut self, spec: F) -> Self
+    where
+        F: Fn(Vec<M::Atom>) -> Prop<M::Atom> + 'static,
+    {
+        self.gatekeeper.spec = Box::new(spec);
+        self
+    }
+    pub fn with_performance<F>(mut self, performance: F) -> Self
+    where
+        F: Fn(&[M::Atom]) -> f64 + 'static,
+    {
+        self.gatekeeper.performance = Box::new(performance);
+        self
+    }
+    /// The action applied to the world when no proposal is certified. Defaults to
+    /// `M::Action::default()`, which for traffic is `Phase::AllRed`.
+    pub fn with_fallback(mut self, fallback: M::Action) -> Self {
+        self.gatekeeper.fallback = fallback;
+        self
+    }
+    /// How many proposals to check per decision period before giving up and applying the fallback.
+    pub fn with_max_rejections(mut self, max_rejections: u32) -> Self {
+        self.gatekeeper.max_rejections = max_rejections;
+        self
+    }
+    /// Start from a `Fidelity` with custom thresholds for flagging the simulation.
+    pub fn with_fidelity(mut self, fidelity: Fidelity) -> Self {
+        self.gatekeeper.fidelity = fidelity;
+        self
+    }
+    /// Every random decision the gatekeeper makes, in the simulation or in the world, is drawn
+    /// from this seed.
+    pub fn with_seed(mut self, seed: u64) -> Self {
+        self.gatekeeper.rng = Rng::seed_from_u64(seed);
+        self
+    }
+    /// Carry on drawing from `rng`, e.g. one restored from a checkpoint.
+    pub fn with_rng(mut self, rng: Rng) -> Self {
+        self.gatekeeper.rng = rng;
+        self
+    }
+    pub fn build(self) -> Gatekeeper<C, M, W> {
+        self.gatekeeper
+    }
+}
+
+pub(crate) static EPSILON: f64 = 1e-5;
+static MAX_REJECTIONS: u32 = 16;
+
+/// What happened at one decision period of an episode.
+#[derive(Clone, Debug)]
+pub struct Decision<A> {
+    proposed: A,
+    applied: A,
+    intervened: bool,
+    num_rejections: u32,
+}
+
+impl<A> Decision<A> {
+    /// The action the controller proposed this period. In `run_episode_exhaustive`, which
+    /// bypasses the controller, it is the best ranked action instead, certified or not.
+    pub fn proposed(&self) -> &A {
+        &self.proposed
+    }
+    /// The action that was actually run in the world: the proposal, an alternative certified in
+    /// its place, or the fallback.
+    pub fn applied(&self) -> &A {
+        &self.applied
+    }
+    /// True when every proposal was rejected and the fallback was applied instead.
+    pub fn intervened(&self) -> bool {
+        self.intervened
+    }
+    pub fn num_rejections(&self) -> u32 {
+        self.num_rejections
+    }
+}
+
+/// A closed-loop run of the world, one atom and one `Decision` per decision period.
+#[derive(Clone, Debug)]
+pub struct Episode<A, T> {
+    trajectory: Vec<T>,
+    decisions: Vec<Decision<A>>,
+}
+
+impl<A, T> Default for Episode<A, T> {
+    fn default() -> Self {
+        Episode {
+            trajectory: Vec::new(),
+            decisions: Vec::new(),
+        }
+    }
+}
+
+impl<A, T> Episode<A, T> {
+    pub fn trajectory(&self) -> &[T] {
+        &self.trajectory
+    }
+    pub fn decisions(&self) -> &[Decision<A>] {
+        &self.decisions
+    }
+    pub fn num_interventions(&self) -> usize {
+        self.decisions
+            .iter()
+            .filter(|decision| decision.intervened())
+            .count()
+    }
+}
+
+/// Mean valuation of `spec` over every time step of `trajectory`.
+pub(crate) fn evaluate<T, F>(spec: &F, trajectory: Vec<T>) -> Valuation
+where
+    T: Atomic,
+    F: Fn(Vec<T>) -> Prop<T> + ?Sized,
+{
+    let time_horizon = trajectory.clone().len();
+    (0..time_horizon)
+        .map(|time| interpret(spec(trajectory.clone()), time))
+        .sum::<f64>()
+        / time_horizon as f64
+}
+
+/// An action together with how it fared across several rollouts from the same snapshot.
+#[derive(Clone, Debug)]
+pub struct Scored<A> {
+    action: A,
+    valuation: Valuation,
+    performance: f64,
+}
+
+impl<A> Scored<A> {
+    pub fn action(&self) -> &A {
+        &self.action
+    }
+    /// The worst valuation of the spec over the rollouts.
+    pub fn valuation(&self) -> Valuation {
+        self.valuation
+    }
+    /// The mean performance over the rollouts.
+    pub fn performance(&self) -> f64 {
+        self.performance
+    }
+    pub fn certified(&self) -> bool {
+        self.valuation > 1.0 - EPSILON
+    }
+}
+
+/// Certified actions first, then by performance, then by valuation.
+fn rank<A>(a: &Scored<A>, b: &Scored<A>) -> Ordering {
+    b.certified()
+        .cmp(&a.certified())
+        .then(
+            b.performance
+                .partial_cmp(&a.performance)
+                .unwrap_or(Ordering::Equal),
+        )
+        .then(
+            b.valuation
+                .partial_cmp(&a.valuation)
+                .unwrap_or(Ordering::Equal),
+        )
+}
+
+impl<C, M, W> Gatekeeper<C, M, W>
+where
+    C: Controller<Observation = M::Observation, Action = M::Action>,
+    M: Model,
+    W: Model<State = M::State, Action = M::Action, Observation = M::Observation, Atom = M::Atom>,
+{
+    pub(crate) fn evaluate(&self, trajectory: Vec<M::Atom>) -> Valuation {
+        evaluate(&|atom| self.spec_at(atom), trajectory)
+    }
+
+    /// Certify the controller's proposal for one decision period and apply it, or the fallback
+    /// when nothing is certified within `max_rejections`.
+    pub fn run(&mut self) -> Decision<M::Action> {
+        let mut prng = std::mem::take(&mut self.rng);
+        let snapshot = self.world.snapshot();
+        let (decision, trajectory_ofsim) = self.decide(&mut prng);
+        self.apply(&snapshot, &decision, trajectory_ofsim, &mut prng);
+        if cfg().get("debug").unwrap() {
+            println!("Number of rejections: {}", decision.num_rejections());
+        }
+        self.rng = prng;
+        decision
+    }
+
+    /// Take the controller's proposal for this period and certify it in simulation from the
+    /// world's current state. When it is rejected, alternatives are drawn from a copy of the
+    /// controller, so that the controller itself only advances one period per decision, until
+    /// one is certified or `max_rejections` have been turned down. Also returns the simulated
+    /// trajectory of the certified action, if there was one.
+    fn decide(&mut self, prng: &mut Rng) -> (Decision<M::Action>, Vec<M::Atom>) {
+        let snapshot = self.world.snapshot();
+        let observation = self.world.observe();
+        let proposed = self.controller.select_action(&observation, prng);
+        let mut alternatives = self.controller.clone();
+        let mut candidate = proposed.clone();
+        let mut num_rejections = 0;
+        loop {
+            let trajectory_ofsim = self
+                .simulation
+                .rollout_from(&snapshot, candidate.clone(), prng);
+            if self.evaluate(trajectory_ofsim.clone()) > 1.0 - EPSILON {
+                let decision = Decision {
+                    proposed,
+                    applied: candidate,
+                    intervened: false,
+                    num_rejections,
+                };
+                return (decision, trajectory_ofsim);
+            }
+            num_rejections += 1;
+            if num_rejections >= self.max_rejections {
+                let decision = Decision {
+                    proposed,
+                    applied: self.fallback.clone(),
+                    intervened: true,
+                    num_rejections,
+                };
+                return (decision, Vec::new());
+            }
+            candidate = alternatives.select_action(&observation, prng);
+        }
+    }
+
+    fn run_episode_with(
+        &mut self,
+        num_periods: u32,
+        gatekept: bool,
+    ) -> Episode<M::Action, M::Atom> {
+        let mut prng = std::mem::take(&mut self.rng);
+        let mut episode = Episode::default();
+        for _ in 0..num_periods {
+            let snapshot = self.world.snapshot();
+            let (decision, trajectory_ofsim) = if gatekept {
+                self.decide(&mut prng)
+            } else {
+                let observation = self.world.observe();
+                let proposed = self.controller.select_action(&observation, &mut prng);
+                let decision = Decision {
+                    applied: proposed.clone(),
+                    proposed,
+                    intervened: false,
+                    num_rejections: 0,
+                };
+                (decision, Vec::new())
+            };
+            let entry = self.apply(&snapshot, &decision, trajectory_ofsim, &mut prng);
+            if cfg().get("debug").unwrap() && decision.intervened() {
+                println!(
+                    "No proposal certified after {} rejections, applied fallback",
+                    decision.num_rejections()
+                );
+            }
+            episode.trajectory.push(entry);
+            episode.decisions.push(decision);
+        }
+        self.rng = prng;
+        episode
+    }
+
+    /// Step the world from `snapshot` with the decision's applied action, and record how well
+    /// the simulation predicted it. `trajectory_ofsim` is the applied action's simulated
+    /// trajectory, or empty to roll one out here.
+    fn apply(
+        &mut self,
+        snapshot: &M::State,
+        decision: &Decision<M::Action>,
+        trajectory_ofsim: Vec<M::Atom>,
+        prng: &mut Rng,
+    ) -> M::Atom {
+        let trajectory_ofsim = if trajectory_ofsim.is_empty() {
+            self.simulation
+                .rollout_from(snapshot, decision.applied.clone(), &mut prng.clone())
+        } else {
+            trajectory_ofsim
+        };
+        // What the world does over the same horizon, drawing the same numbers as the step
+        // below so that its first period is the one the world actually goes through.
+        let trajectory_ofworld =
+            self.world
+                .rollout_from(snapshot, decision.applied.clone(), &mut prng.clone());
+        let verdict = (
+            self.evaluate(trajectory_ofsim.clone()),
+            self.evaluate(trajectory_ofworld),
+        );
+        let entry = self.world.step(decision.applied.clone(), prng);
+        // Only the first period of the rollout predicts what the world just did.
+        self.record_fidelity(
+            &trajectory_ofsim,
+            std::slice::from_ref(&entry),
+            Some(verdict),
+        );
+        entry
+    }
+
+    /// Receding-horizon control of the world: every decision period, observe the world, let the
+    /// controller propose, check the proposal in simulation and apply it (or the fallback).
+    pub fn run_episode(&mut self, num_periods: u32) -> Episode<M::Action, M::Atom> {
+        self.run_episode_with(num_periods, true)
+    }
+
+    /// The same closed loop as `run_episode`, but every proposal goes straight to the world.
+    pub fn run_episode_ungatekept(&mut self, num_periods: u32) -> Episode<M::Action, M::Atom> {
+        self.run_episode_with(num_periods, false)
+    }
+}
+
+impl<C, M, W> Gatekeeper<C, M, W>
+where
+    C: Controller<Observation = M::Observation, Action = M::Action>,
+    M: FiniteActions,
+    W: Model<State = M::State, Action = M::Action, Observation = M::Observation, Atom = M::Atom>,
+{
+    fn rank_actions_with(&self, num_samples: u32, prng: &mut Rng) -> Vec<Scored<M::Action>> {
+        assert!(
+            num_samples > 0,
+            "can't score an action without rolling it out"
+        );
+        let snapshot = self.world.snapshot();
+        let mut ranked: Vec<Scored<M::Action>> = self
+            .simulation
+            .actions()
+            .into_iter()
+            .map(|action| {
+                let mut valuation = f64::MAX;
+                let mut performance = 0.0;
+                for _ in 0..num_samples {
+                    let trajectory_ofsim =
+                        self.simulation
+                            .rollout_from(&snapshot, action.clone(), prng);
+                    performance += (self.performance)(&trajectory_ofsim);
+                    valuation = valuation.min(self.evaluate(trajectory_ofsim));
+                }
+                Scored {
+                    action,
+                    valuation,
+                    performance: performance / num_samples as f64,
+                }
+            })
+            .collect();
+        ranked.sort_by(rank);
+        ranked
+    }
+
+    /// Score every action in the action space with `num_samples` rollouts from the world's
+    /// current state, best first. A complete alternative to rejection sampling. The rollouts are
+    /// sampled from the gatekeeper's seeded `Rng`, so the same seed gives the same ranking.
+    /// Panics if `num_samples` is 0.
+    pub fn rank_actions(&mut self, num_samples: u32) -> Vec<Scored<M::Action>> {
+        let mut prng = std::mem::take(&mut self.rng);
+        let ranked = self.rank_actions_with(num_samples, &mut prng);
+        self.rng = prng;
+        ranked
+    }
+
+    /// The best performing action that is certified, if there is one.
+    pub fn best_certified_action(&mut self, num_samples: u32) -> Option<Scored<M::Action>> {
+        self.rank_actions(num_samples)
+            .into_iter()
+            .find(|scored| scored.certified())
+    }
+
+    /// Like `run_episode`, but each period the controller is bypassed and the best certified
+    /// action is applied, or the fallback if no action is certified.
+    pub fn run_episode_exhaustive(
+        &mut self,
+        num_periods: u32,
+        num_samples: u32,
+    ) -> Episode<M::Action, M::Atom> {
+        let mut prng = std::mem::take(&mut self.rng);
+        let mut episode = Episode::default();
+        for _ in 0..num_periods {
+            let snapshot = self.world.snapshot();
+            let ranked = self.rank_actions_with(num_samples, &mut prng);
+            let num_rejections = ranked.iter().filter(|scored| !scored.certified()).count() as u32;
+            let decision = match ranked.into_iter().next() {
+                Some(best) if best.certified() => Decision {
+                    applied: best.action.clone(),
+                    proposed: best.action,
+                    intervened: false,
+                    num_rejections,
+                },
+                best => Decision {
+                    proposed: best.map_or_else(|| self.fallback.clone(), |best| best.action),
+                    applied: self.fallback.clone(),
+                    intervened: true,
+                    num_rejections,
+                },
+            };
+            let entry = self.apply(&snapshot, &decision, Vec::new(), &mut prng);
+            episode.trajectory.push(entry);
+            episode.decisions.push(decision);
+        }
+        self.rng = prng;
+        episode
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use crate::gatekeeper::model::ActionDistance;
+    use crate::logic::types::Atomic;
+    use crate::traffic::intersection::IntersectionBuilder;
+    use crate::traffic::light::Phase;
+    use crate::traffic::simulation::{Random, Simulation, SimulationBuilder};
+    use crate::traffic::trajectory::throughput;
+    use std::fmt;
+
+    pub(crate) fn small_simulation() -> Simulation<Random> {
+        SimulationBuilder::<Random>::new()
+            .with_intersection(IntersectionBuilder::new().build())
+            .with_max_cars(16)
+            .with_drive_steps_per_lightswitch(8)
+            .with_max_steps(4)
+            .build()
+    }
+
+    #[test]
+    fn run_episode_trivial_spec_certifies_every_proposal() {
+        let mut gatekeeper: Gatekeeper<Random, Simulation<Random>> =
+            GatekeeperBuilder::new(small_simulation(), small_simulation()).build();
+        let episode = gatekeeper.run_episode(8);
+        assert_eq!(episode.trajectory().len(), 8);
+        assert_eq!(episode.decisions().len(), 8);
+        assert_eq!(episode.num_interventions(), 0);
+        assert_eq!(
+            episode.cumulative_crashes().last().copied(),
+            Some(episode.num_crashes())
+        );
+        assert_eq!(gatekeeper.fidelity().num_steps(), 8);
+        assert_eq!(gatekeeper.fidelity().channel_errors().len(), 2);
+    }
+
+    #[test]
+    fn run_episode_unsatisfiable_spec_always_falls_back() {
+        let fallback = Phase::EastWest;
+        let mut gatekeeper: Gatekeeper<Random, Simulation<Random>> =
+            GatekeeperBuilder::new(small_simulation(), small_simulation())
+                .with_spec(|_| Prop::ff())
+                .with_fallback(fallback)
+                .with_max_rejections(3)
+                .build();
+        let episode = gatekeeper.run_episode(4);
+        assert_eq!(episode.num_interventions(), 4);
+        for decision in episode.decisions() {
+            assert_eq!(decision.applied(), &fallback);
+            assert_eq!(decision.num_rejections(), 3);
+        }
+    }
+
+    #[test]
+    fn run_episode_ungatekept_never_intervenes() {
+        let mut gatekeeper: Gatekeeper<Random, Simulation<Random>> =
+            GatekeeperBuilder::new(small_simulation(), small_simulation())
+                .with_spec(|_| Prop::ff())
+                .build();
+        let episode = gatekeeper.run_episode_ungatekept(4);
+        assert_eq!(episode.num_interventions(), 0);
+        assert_eq!(episode.trajectory().len(), 4);
+    }
+
+    /// A toy domain with nothing to do with traffic: a walk that must stay at or below height 2.
+    #[derive(Clone, Debug, PartialEq, Eq, Hash)]
+    pub(crate) struct Height(i32);
+    impl fmt::Display for Height {
+        fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            write!(f, "h{}", self.0)
+        }
+    }
+    impl Atomic for Height {
+        fn val(&self) -> Valuation {
+            if self.0 <= 2 {
+                1.0
+            } else {
+                0.0
+            }
+        }
+    }
+
+    #[derive(Clone, Default)]
+    pub(crate) struct Walk {
+        pub(crate) height: i32,
+    }
+    impl Model for Walk {
+        type State = i32;
+        type Action = i32;
+        type Observation = i32;
+        type Atom = Height;
+        fn snapshot(&self) -> i32 {
+            self.height
+        }
+        fn observe(&self) -> i32 {
+            self.height
+        }
+        fn sync_from(&mut self, snapshot: &i32) {
+            self.height = *snapshot;
+        }
+        fn step(&mut self, action: i32, _rng: &mut Rng) -> Height {
+            self.height += action;
+            Height(self.height)
+        }
+        fn rollout(&mut self, action: i32, rng: &mut Rng) -> Vec<Height> {
+            vec![self.step(action, rng)]
+        }
+    }
+
+    impl FiniteActions for Walk {
+        fn actions(&self) -> Vec<i32> {
+            vec![-1, 0, 1]
+        }
+    }
+
+    impl ActionDistance for i32 {
+        fn distance(&self, other: &i32) -> u32 {
+            self.abs_diff(*other)
+        }
+    }
+
+    #[derive(Clone, Default)]
+    pub(crate) struct Up;
+    impl Controller for Up {
+        type Observation = i32;
+        type Action = i32;
+        fn select_action(&mut self, _height: &i32, _rng: &mut Rng) -> i32 {
+            1
+        }
+    }
+
+    /// Counts its proposals, alternating between climbing and standing still.
+    #[derive(Clone, Default)]
+    struct UpThenStay {
+        num_proposals: i32,
+    }
+    impl Controller for UpThenStay {
+        type Observation = i32;
+        type Action = i32;
+        fn select_action(&mut self, _height: &i32, _rng: &mut Rng) -> i32 {
+            self.num_proposals += 1;
+            self.num_proposals % 2
+        }
+    }
+
+    #[test]
+    fn run_unsatisfiable_spec_falls_back() {
+        let fallback = Phase::EastWest;
+        let mut gatekeeper: Gatekeeper<Random, Simulation<Random>> =
+            GatekeeperBuilder::new(small_simulation(), small_simulation())
+                .with_spec(|_| Prop::ff())
+                .with_fallback(fallback)
+                .with_max_rejections(3)
+                .build();
+        let decision = gatekeeper.run();
+        assert_eq!(decision.applied(), &fallback);
+        assert!(decision.intervened());
+        assert_eq!(gatekeeper.fidelity().num_steps(), 1);
+    }
+
+    #[test]
+    fn rejected_proposal_is_replaced_without_advancing_the_controller() {
+        let mut gatekeeper: Gatekeeper<UpThenStay, Walk> =
+            GatekeeperBuilder::new(Walk::default(), Walk { height: 2 })
+                .with_spec(Prop::var)
+                .build();
+        let episode = gatekeeper.run_episode(2);
+        let first = &episode.decisions()[0];
+        assert_eq!((*first.proposed(), *first.applied()), (1, 0));
+        assert_eq!(first.num_rejections(), 1);
+        assert!(!first.intervened());
+        assert_eq!(*episode.decisions()[1].proposed(), 0);
+        assert_eq!(gatekeeper.controller().num_proposals, 2);
+    }
+
+    #[test]
+    fn run_episode_toy_domain_falls_back_at_the_ceiling() {
+        let mut gatekeeper: Gatekeeper<Up, Walk> =
+            GatekeeperBuilder::new(Walk::default(), Walk::default())
+                .with_spec(Prop::var)
+                .with_max_rejections(1)
+                .build();
+        let episode = gatekeeper.run_episode(5);
+        let applied: Vec<i32> = episode
+            .decisions()
+            .iter()
+            .map(|decision| *decision.applied())
+            .collect();
+        assert_eq!(applied, vec![1, 1, 0, 0, 0]);
+        assert_eq!(episode.num_interventions(), 3);
+        assert_eq!(episode.trajectory().last(), Some(&Height(2)));
+    }
+
+    fn walk_gatekeeper(height: i32) -> Gatekeeper<Up, Walk> {
+        GatekeeperBuilder::new(Walk::default(), Walk { height })
+            .with_spec(Prop::var)
+            .with_performance(|trajectory: &[Height]| trajectory[0].0 as f64)
+            .build()
+    }
+
+    #[test]
+    fn rank_actions_orders_certified_by_performance() {
+        let ranked: Vec<i32> = walk_gatekeeper(0)
+            .rank_actions(2)
+            .iter()
+            .map(|scored| *scored.action())
+            .collect();
+        assert_eq!(ranked, vec![1, 0, -1]);
+    }
+
+    #[test]
+    fn best_certified_action_skips_uncertified() {
+        let mut gatekeeper = walk_gatekeeper(2);
+        let ranked = gatekeeper.rank_actions(1);
+        assert_eq!(*ranked.last().unwrap().action(), 1);
+        assert!(!ranked.last().unwrap().certified());
+        assert_eq!(
+            gatekeeper
+                .best_certified_action(1)
+                .map(|scored| scored.action),
+            Some(0)
+        );
+    }
+
+    #[test]
+    #[should_panic(expected = "without rolling it out")]
+    fn rank_actions_needs_a_sample() {
+        walk_gatekeeper(0).best_certified_action(0);
+    }
+
+    #[test]
+    fn run_episode_exhaustive_climbs_to_the_ceiling_and_stays() {
+        let mut gatekeeper = walk_gatekeeper(0);
+        let episode = gatekeeper.run_episode_exhaustive(4, 1);
+        let applied: Vec<i32> = episode
+            .decisions()
+            .iter()
+            .map(|decision| *decision.applied())
+            .collect();
+        assert_eq!(applied, vec![1, 1, 0, 0]);
+        assert_eq!(episode.num_interventions(), 0);
+        assert_eq!(gatekeeper.fidelity().num_steps(), 4);
+    }
+
+    #[test]
+    fn rank_actions_traffic_covers_every_phase() {
+        let mut gatekeeper: Gatekeeper<Random, Simulation<Random>> =
+            GatekeeperBuilder::new(small_simulation(), small_simulation())
+                .with_performance(throughput)
+                .build();
+        let ranked = gatekeeper.rank_actions(1);
+        assert_eq!(ranked.len(), 4);
+        assert!(ranked.iter().all(|scored| scored.certified()));
+    }
+
+    #[test]
+    fn run_episode_same_seed_same_episode() {
+        let run = |seed: u64| {
+            let mut gatekeeper: Gatekeeper<Random, Simulation<Random>> =
+                GatekeeperBuilder::new(small_simulation(), small_simulation())
+                    .with_seed(seed)
+                    .build();
+            gatekeeper.run_episode(4).trajectory().to_vec()
+        };
+        assert_eq!(run(3), run(3));
+    }
+}
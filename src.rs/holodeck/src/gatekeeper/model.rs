@@ -0,0 +1,64 @@
+//! What the gatekeeper needs to know about a domain: how to propose actions, and how to
+//! snapshot, step and roll out the system those actions are applied to.
+use crate::data::prng::Rng;
+use crate::logic::types::Atomic;
+
+/// Proposes actions from what it can observe. The gatekeeper decides whether they reach the
+/// world.
+pub trait Controller: Default + Clone {
+    type Observation;
+    type Action: Clone + Default;
+    fn select_action(&mut self, observation: &Self::Observation, rng: &mut Rng) -> Self::Action;
+}
+
+/// A system the gatekeeper can certify actions against, either as the simulation or as the world.
+pub trait Model: Clone {
+    /// Everything needed to start a rollout from where the system currently is.
+    type State: Clone;
+    type Action: Clone + Default;
+    /// What a controller is shown of the system before it acts.
+    type Observation;
+    /// What one decision period of the system looks like to the spec.
+    type Atom: Atomic + 'static;
+
+    fn snapshot(&self) -> Self::State;
+
+    fn observe(&self) -> Self::Observation;
+
+    /// The numbers in an atom worth comparing between simulation and world. Defaults to just
+    /// the atom's valuation.
+    fn channels(atom: &Self::Atom) -> Vec<f64> {
+        vec![atom.val()]
+    }
+
+    /// Overwrite the state of this system, typically with a snapshot taken from the world.
+    fn sync_from(&mut self, snapshot: &Self::State);
+
+    /// Apply `action` for one decision period and report what happened.
+    fn step(&mut self, action: Self::Action, rng: &mut Rng) -> Self::Atom;
+
+    /// Apply `action`, then run for the model's own horizon, recording every decision period.
+    fn rollout(&mut self, action: Self::Action, rng: &mut Rng) -> Vec<Self::Atom>;
+
+    /// Roll out `action` starting from `snapshot`, leaving this system's own state untouched.
+    fn rollout_from(
+        &self,
+        snapshot: &Self::State,
+        action: Self::Action,
+        rng: &mut Rng,
+    ) -> Vec<Self::Atom> {
+        let mut model = self.clone();
+        model.sync_from(snapshot);
+        model.rollout(action, rng)
+    }
+}
+
+/// A model whose action space is small enough to check every action in it.
+pub trait FiniteActions: Model {
+    fn actions(&self) -> Vec<Self::Action>;
+}
+
+/// How far apart two actions are, for finding the certified action nearest to a rejected one.
+pub trait ActionDistance {
+    fn distance(&self, other: &Self) -> u32;
+}
@@ -0,0 +1,261 @@
+//! Wrap any controller so that its actions only reach the world when the gatekeeper certifies
+//! them, changing them as little as possible when it doesn't. A controller is only shown an
+//! observation, so whoever drives the shield should sync it from the world's state before every
+//! decision; otherwise it certifies against the state it was last synced from.
+use crate::data::prng::Rng;
+use crate::gatekeeper::model::{ActionDistance, Controller, FiniteActions};
+use crate::gatekeeper::{evaluate, EPSILON};
+use crate::logic::syntax::Prop;
+use std::rc::Rc;
+
+/// Shared rather than boxed like `Spec`, since controllers have to be `Clone`.
+type SharedSpec<T> = Rc<dyn Fn(Vec<T>) -> Prop<T>>;
+
+/// A controller that passes the inner controller's action through when it is certified in
+/// simulation, and otherwise substitutes the nearest certified action.
+#[derive(Clone)]
+pub struct Shield<C, M>
+where
+    C: Controller<Observation = M::Observation, Action = M::Action>,
+    M: FiniteActions,
+{
+    inner: C,
+    simulation: M,
+    spec: SharedSpec<M::Atom>,
+    fallback: M::Action,
+    num_decisions: u32,
+    num_interventions: u32,
+}
+
+pub struct ShieldBuilder<C, M>
+where
+    C: Controller<Observation = M::Observation, Action = M::Action>,
+    M: FiniteActions,
+{
+    shield: Shield<C, M>,
+}
+
+impl<C, M> ShieldBuilder<C, M>
+where
+    C: Controller<Observation = M::Observation, Action = M::Action>,
+    M: FiniteActions,
+{
+    pub fn new(inner: C, simulation: M) -> Self {
+        ShieldBuilder {
+            shield: Shield {
+                inner,
+                simulation,
+                spec: Rc::new(|_| Prop::True),
+                fallback: M::Action::default(),
+                num_decisions: 0,
+                num_interventions: 0,
+            },
+        }
+    }
+    pub fn with_spec<F>(mut self, spec: F) -> Self
+    where
+        F: Fn(Vec<M::Atom>) -> Prop<M::Atom> + 'static,
+    {
+        self.shield.spec = Rc::new(spec);
+        self
+    }
+    /// The action taken when no action at all is certified.
+    pub fn with_fallback(mut self, fallback: M::Action) -> Self {
+        self.shield.fallback = fallback;
+        self
+    }
+    pub fn build(self) -> Shield<C, M> {
+        self.shield
+    }
+}
+
+impl<C, M> Default for Shield<C, M>
+where
+    C: Controller<Observation = M::Observation, Action = M::Action>,
+    M: FiniteActions + Default,
+{
+    fn default() -> Self {
+        ShieldBuilder::new(C::default(), M::default()).build()
+    }
+}
+
+impl<C, M> Shield<C, M>
+where
+    C: Controller<Observation = M::Observation, Action = M::Action>,
+    M: FiniteActions,
+{
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+    /// Certify the next decision against `snapshot`, which should be the world's current state.
+    pub fn sync_from(&mut self, snapshot: &M::State) {
+        self.simulation.sync_from(snapshot);
+    }
+    pub fn num_decisions(&self) -> u32 {
+        self.num_decisions
+    }
+    /// How many times the inner controller's action was replaced.
+    pub fn num_interventions(&self) -> u32 {
+        self.num_interventions
+    }
+    pub fn intervention_rate(&self) -> f64 {
+        self.num_interventions() as f64 / self.num_decisions().max(1) as f64
+    }
+
+    fn certifies(&self, action: &M::Action, rng: &mut Rng) -> bool {
+        let trajectory_ofsim =
+            self.simulation
+                .rollout_from(&self.simulation.snapshot(), action.clone(), rng);
+        evaluate(&*self.spec, trajectory_ofsim) > 1.0 - EPSILON
+    }
+}
+
+impl<C, M> Controller for Shield<C, M>
+where
+    C: Controller<Observation = M::Observation, Action = M::Action>,
+    M: FiniteActions + Default,
+    M::Action: ActionDistance,
+{
+    type Observation = M::Observation;
+    type Action = M::Action;
+
+    /// Certified against the state last passed to `sync_from`, or the simulation's own initial
+    /// state when driven as a plain controller that is never synced.
+    fn select_action(&mut self, observation: &M::Observation, rng: &mut Rng) -> M::Action {
+        let proposed = self.inner.select_action(observation, rng);
+        self.num_decisions += 1;
+        if self.certifies(&proposed, rng) {
+            return proposed;
+        }
+        self.num_interventions += 1;
+        let mut candidates = self.simulation.actions();
+        candidates.retain(|action| action.distance(&proposed) > 0);
+        candidates.sort_by_key(|action| action.distance(&proposed));
+        candidates
+            .into_iter()
+            .find(|action| self.certifies(action, rng))
+            .unwrap_or_else(|| self.fallback.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::cfg;
+    use crate::gatekeeper::model::Model;
+    use crate::gatekeeper::tests::{small_simulation, Up, Walk};
+    use crate::traffic::controllers::FixedTime;
+    use crate::traffic::intersection::IntersectionBuilder;
+    use crate::traffic::light::{Light, Phase};
+    use crate::traffic::simulation::{Random, Simulation, SimulationBuilder};
+    use rand::SeedableRng;
+
+    #[test]
+    fn shield_passes_certified_actions_through() {
+        let mut shield = ShieldBuilder::new(Up, Walk { height: 0 })
+            .with_spec(Prop::var)
+            .build();
+        let mut prng = Rng::seed_from_u64(0);
+        shield.sync_from(&0);
+        assert_eq!(shield.select_action(&0, &mut prng), 1);
+        assert_eq!(shield.num_decisions(), 1);
+        assert_eq!(shield.num_interventions(), 0);
+    }
+
+    #[test]
+    fn shield_substitutes_nearest_certified_action() {
+        let mut shield = ShieldBuilder::new(Up, Walk::default())
+            .with_spec(Prop::var)
+            .build();
+        let mut prng = Rng::seed_from_u64(0);
+        for _ in 0..2 {
+            shield.sync_from(&2);
+            assert_eq!(shield.select_action(&2, &mut prng), 0);
+        }
+        assert_eq!(shield.num_interventions(), 2);
+        assert_eq!(shield.intervention_rate(), 1.0);
+    }
+
+    #[test]
+    fn shield_without_sync_certifies_against_the_last_state() {
+        let mut shield = ShieldBuilder::new(Up, Walk::default())
+            .with_spec(Prop::var)
+            .build();
+        let mut prng = Rng::seed_from_u64(0);
+        assert_eq!(shield.select_action(&0, &mut prng), 1);
+        shield.sync_from(&2);
+        for _ in 0..2 {
+            assert_eq!(shield.select_action(&0, &mut prng), 0);
+        }
+        assert_eq!(shield.num_decisions(), 3);
+        assert_eq!(shield.num_interventions(), 2);
+    }
+
+    #[test]
+    fn shield_drives_a_simulation_as_a_light_controller() {
+        let shield = ShieldBuilder::new(Random, small_simulation())
+            .with_spec(|_| Prop::ff())
+            .with_fallback(Phase::EastWest)
+            .build();
+        let mut simulation = SimulationBuilder::new()
+            .with_controller(shield)
+            .with_drive_steps_per_lightswitch(4)
+            .with_max_steps(2)
+            .build();
+        simulation.run(&mut Rng::seed_from_u64(0));
+        assert_eq!(simulation.controller().num_decisions(), 2);
+    }
+
+    #[test]
+    fn shield_traffic_unsatisfiable_spec_falls_back() {
+        let fallback = Phase::EastWest;
+        let mut shield = ShieldBuilder::new(Random, small_simulation())
+            .with_spec(|_| Prop::ff())
+            .with_fallback(fallback)
+            .build();
+        let mut prng = Rng::seed_from_u64(0);
+        let world = small_simulation();
+        shield.sync_from(&world.snapshot());
+        assert_eq!(shield.select_action(&world.observe(), &mut prng), fallback);
+        assert_eq!(shield.num_interventions(), 1);
+    }
+
+    #[test]
+    fn shield_rejects_a_phase_change_into_the_cars_present() {
+        let light_coord: u32 = cfg().get("light_coord").unwrap();
+        // Cars from the north and the west at their stop lines, with north-south green. Without
+        // all-red clearance, switching to east-west now puts both in the box.
+        let mut world = IntersectionBuilder::new()
+            .with_min_green(0)
+            .with_yellow(1)
+            .with_all_red(0)
+            .build();
+        world.request_phase(Phase::NorthSouth);
+        world.spawn_car(Light::N);
+        world.spawn_car(Light::W);
+        for _ in 0..light_coord {
+            world.advance();
+        }
+        let simulation: Simulation<Random> = SimulationBuilder::new()
+            .with_drive_steps_per_lightswitch(4)
+            .with_max_steps(1)
+            .build();
+        let east_west = FixedTime::new(vec![(Phase::EastWest, 1)]);
+        let mut shield = ShieldBuilder::new(east_west, simulation.clone())
+            .with_spec(Prop::var)
+            .build();
+        let mut prng = Rng::seed_from_u64(0);
+        // From the empty default intersection there is nothing to crash into.
+        shield.sync_from(&simulation.snapshot());
+        assert_eq!(
+            shield.select_action(&simulation.observe(), &mut prng),
+            Phase::EastWest
+        );
+        shield.sync_from(&world);
+        assert_ne!(
+            shield.select_action(&world.observe(), &mut prng),
+            Phase::EastWest
+        );
+        assert_eq!(shield.num_interventions(), 1);
+    }
+}
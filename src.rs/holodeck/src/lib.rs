@@ -1,6 +1,7 @@
 //! Traffic simulation for the gatekeeper example
 pub mod cfg;
 pub mod data;
+pub mod env;
 pub mod gatekeeper;
 pub mod logic;
 pub mod traffic;
@@ -4,6 +4,7 @@ use crate::logic::syntax::Prop;
 use crate::logic::types::{Atomic, Time, TimeWindow, Valuation};
 
 /// pUq |=> sup { interpret(*q, t').min(inf{interpret(*p, t'') | t <= t'' < t'}) | t' >= time }
+/// where t' runs to the end of the shortest trajectory in p or q.
 fn interpret_until<T: Atomic>(
     interpret_fn: impl Interpreter<T>,
     p: Prop<T>,
@@ -19,11 +20,13 @@ fn interpret_until<T: Atomic>(
             interpret_fn(q.clone(), t).min(p_inf)
         }
     };
-    approximate_supremum(
-        sup_of,
-        q.clone(),
-        TimeWindow::new(time, cfg().get::<Time>("max_timestamp").unwrap() - 1),
-    )
+    let end = p
+        .horizon()
+        .into_iter()
+        .chain(q.horizon())
+        .min()
+        .unwrap_or_else(|| cfg().get::<Time>("max_timestamp").unwrap() - 1);
+    approximate_supremum(sup_of, q.clone(), TimeWindow::new(time, end))
 }
 /// goedel's fuzzy logic (see LDL paper) with a custom `until` operator
 pub(crate) fn interpret<T: Atomic>(formula: Prop<T>, time: Time) -> Valuation {
@@ -102,5 +102,37 @@ mod tests {
         );
     }
 
+    #[test]
+    fn until_looks_up_to_the_last_entry_of_the_trajectory() {
+        let prop = syntax::Prop::until(
+            syntax::Prop::var(vec![MockAtomicE::B; 4]),
+            syntax::Prop::var(vec![
+                MockAtomicE::B,
+                MockAtomicE::B,
+                MockAtomicE::B,
+                MockAtomicE::A,
+            ]),
+        );
+        assert!(float_equiv(interpreter::interpret(prop, 3), 1.0));
+    }
+
+    #[test]
+    fn always_holds_at_the_last_timestamp_of_a_full_trajectory() {
+        let max_timestamp: usize = cfg().get("max_timestamp").unwrap();
+        let prop = syntax::Prop::var(vec![MockAtomicE::A; max_timestamp]).always();
+        assert!(float_equiv(
+            interpreter::interpret(prop, max_timestamp - 1),
+            1.0
+        ));
+    }
+
+    #[test]
+    fn always_over_a_short_trajectory() {
+        let prop = syntax::Prop::var(vec![MockAtomicE::A; 4]).always();
+        for time in 0..4 {
+            assert!(float_equiv(interpreter::interpret(prop.clone(), time), 1.0));
+        }
+    }
+
     // TODO: more testing.
 }
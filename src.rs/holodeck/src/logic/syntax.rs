@@ -75,6 +75,18 @@ where
         Prop::Not(Box::new(self))
     }
 
+    /// The length of the shortest trajectory the formula reads, which is as far ahead as it can
+    /// look. None if it reads none.
+    pub fn horizon(&self) -> Option<usize> {
+        match self {
+            Prop::True => None,
+            Prop::Var(x) => Some(x.len()),
+            Prop::Le(x, y) => Some(x.len().min(y.len())),
+            Prop::Not(p) => p.horizon(),
+            Prop::And(p, q) | Prop::Until(p, q) => p.horizon().into_iter().chain(q.horizon()).min(),
+        }
+    }
+
     pub fn ff() -> Self {
         Self::tt().not()
     }
@@ -1,27 +1,135 @@
+use crate::traffic::geometry::{Geometry, LaneId, Zone};
 use crate::traffic::light::{CurrentlyGreen, Light};
+use crate::traffic::movement::Movement;
+use serde::{Deserialize, Serialize};
 
-pub(crate) type CarId = u32;
+pub type CarId = u32;
 pub(crate) type CarPos = u32;
 
-#[derive(Clone, Debug)]
+/// Hands out car ids in increasing order, so no two cars in a run ever share one.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub(crate) struct CarIds {
+    next: CarId,
+}
+
+impl CarIds {
+    pub(crate) fn next(&mut self) -> CarId {
+        let id = self.next;
+        self.next += 1;
+        id
+    }
+    /// How many ids have been handed out.
+    pub(crate) fn num_issued(&self) -> u32 {
+        self.next
+    }
+}
+
+/// What kind of vehicle a car is. Every class takes up one cell, but trucks and buses are heavy:
+/// they only move on every other drive step, and are longer in the continuous model.
+#[derive(Eq, Hash, PartialEq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum VehicleClass {
+    #[default]
+    Car,
+    Truck,
+    Bus,
+    /// Asks the signal for preemption as it approaches.
+    Emergency,
+}
+
+impl VehicleClass {
+    pub fn all() -> [VehicleClass; 4] {
+        [
+            VehicleClass::Car,
+            VehicleClass::Truck,
+            VehicleClass::Bus,
+            VehicleClass::Emergency,
+        ]
+    }
+    /// Drive steps it takes to move on one cell.
+    pub fn steps_per_cell(&self) -> u32 {
+        match self {
+            VehicleClass::Truck | VehicleClass::Bus => 2,
+            VehicleClass::Car | VehicleClass::Emergency => 1,
+        }
+    }
+    /// Bumper to bumper, as a multiple of a car's length.
+    pub fn length_factor(&self) -> f64 {
+        match self {
+            VehicleClass::Truck | VehicleClass::Bus => 1.6,
+            VehicleClass::Car | VehicleClass::Emergency => 1.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
 pub(crate) struct Car {
     pub id: CarId,
     pub light: Light,
+    pub lane: LaneId,
+    pub movement: Movement,
     pub position: CarPos,
+    pub class: VehicleClass,
+    /// Whether the car stood still in the last step.
+    pub standing: bool,
 }
 
 impl Car {
-    pub(crate) fn new(id: CarId, light: Light) -> Self {
+    pub(crate) fn new(id: CarId, light: Light, lane: LaneId, movement: Movement) -> Self {
         Car {
             id,
             light,
+            lane,
+            movement,
             position: 0,
+            class: VehicleClass::default(),
+            standing: false,
         }
     }
 
-    pub(crate) fn advance(&mut self, lights: &CurrentlyGreen) {
-        if lights.contains(&self.light) {
-            self.position += 1;
+    /// Only a car waiting at the stop line needs a green (or yellow) light to move on. Cars upstream of it
+    /// drive up to the line, and cars past it clear the intersection whatever the lights say.
+    pub(crate) fn may_cross(&self, proceeding: &CurrentlyGreen, geometry: &Geometry) -> bool {
+        self.position != geometry.stop_line(&self.light)
+            || proceeding.contains(&(self.light.clone(), self.movement))
+    }
+
+    /// The cell the car would be in at `position`. Cars in one lane queue behind each other up to
+    /// the far side of the intersection, after which each movement has its own exit road.
+    pub(crate) fn cell_at(
+        &self,
+        position: CarPos,
+        geometry: &Geometry,
+    ) -> (Light, LaneId, Option<Movement>, CarPos) {
+        let last_in_box = geometry.last_in_box(&self.light, self.lane, self.movement);
+        let exit = (position > last_in_box).then_some(self.movement);
+        (self.light.clone(), self.lane, exit, position)
+    }
+
+    /// The conflict zone the car is in, if it is inside the box.
+    pub(crate) fn zone(&self, geometry: &Geometry) -> Option<Zone> {
+        geometry.zone(&self.light, self.lane, self.movement, self.position)
+    }
+
+    /// The leg whose crosswalk the car is on, if any: the one it enters by, in the first cell
+    /// past its stop line, or the one it leaves by, in the first cell of its exit road.
+    pub(crate) fn crosswalk(&self, geometry: &Geometry) -> Option<Light> {
+        if self.position == geometry.stop_line(&self.light) + 1 {
+            Some(self.light.clone())
+        } else if self.position == geometry.last_in_box(&self.light, self.lane, self.movement) + 1 {
+            Some(self.light.exit_side(self.movement))
+        } else {
+            None
         }
     }
+
+    /// The leg whose crosswalk the car would be on after advancing one cell, if any.
+    pub(crate) fn crosswalk_ahead(&self, geometry: &Geometry) -> Option<Light> {
+        let mut ahead = self.clone();
+        ahead.advance();
+        ahead.crosswalk(geometry)
+    }
+
+    pub(crate) fn advance(&mut self) {
+        self.position += 1;
+    }
 }
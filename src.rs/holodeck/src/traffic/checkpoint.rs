@@ -0,0 +1,125 @@
+//! A simulation saved part way through a run, together with where its random number generator
+//! had got to, so the run can be resumed later or rewound to that point. Checkpoints are written
+//! as JSON with a format version, and only a checkpoint of the current version can be read back.
+use crate::data::prng::{Rng, RngState};
+use crate::traffic::simulation::{LightController, Simulation};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::io::{self, Read, Write};
+
+/// Bump whenever a change to the simulation's state changes what gets written.
+pub static VERSION: u32 = 2;
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct Checkpoint<C: LightController> {
+    version: u32,
+    simulation: Simulation<C>,
+    rng: RngState,
+}
+
+impl<C: LightController> Checkpoint<C> {
+    pub fn snapshot(simulation: &Simulation<C>, rng: &Rng) -> Self {
+        Checkpoint {
+            version: VERSION,
+            simulation: simulation.clone(),
+            rng: rng.state(),
+        }
+    }
+
+    /// The simulation as it was, and a generator that will make the same draws from here on as
+    /// the one the snapshot was taken with.
+    pub fn restore(&self) -> (Simulation<C>, Rng) {
+        (self.simulation.clone(), Rng::from_state(&self.rng))
+    }
+
+    pub fn version(&self) -> u32 {
+        self.version
+    }
+    /// Drive steps the simulation had run when the snapshot was taken.
+    pub fn time(&self) -> u32 {
+        self.simulation.intersection().time()
+    }
+}
+
+impl<C: LightController + Serialize + DeserializeOwned> Checkpoint<C> {
+    pub fn write(&self, writer: impl Write) -> io::Result<()> {
+        Ok(serde_json::to_writer(writer, self)?)
+    }
+
+    /// Fails on a checkpoint written with another format version.
+    pub fn read(reader: impl Read) -> io::Result<Self> {
+        let value: serde_json::Value = serde_json::from_reader(reader)?;
+        let version = value.get("version").and_then(|version| version.as_u64());
+        if version != Some(VERSION as u64) {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!(
+                    "checkpoint has format version {:?}, expected {}",
+                    version, VERSION
+                ),
+            ));
+        }
+        Ok(serde_json::from_value(value)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::gatekeeper::model::Model;
+    use crate::traffic::controllers::Actuated;
+    use crate::traffic::demand::Demand;
+    use crate::traffic::light::Phase;
+    use crate::traffic::simulation::SimulationBuilder;
+    use rand::SeedableRng;
+
+    fn simulation() -> Simulation<Actuated> {
+        SimulationBuilder::new()
+            .with_controller(Actuated::new(1, 4))
+            .with_demand(Demand::uniform(0.3))
+            .with_drive_steps_per_lightswitch(8)
+            .with_max_steps(6)
+            .build()
+    }
+
+    #[test]
+    fn restored_checkpoint_resumes_the_same_run() {
+        let mut rng = Rng::seed_from_u64(5);
+        let mut simulation = simulation();
+        simulation.rollout(Phase::NorthSouth, &mut rng);
+        let checkpoint = Checkpoint::snapshot(&simulation, &rng);
+        let mut buffer = Vec::new();
+        checkpoint.write(&mut buffer).unwrap();
+
+        let carried_on = simulation.rollout(Phase::EastWest, &mut rng);
+        let (mut restored, mut restored_rng) = Checkpoint::<Actuated>::read(buffer.as_slice())
+            .unwrap()
+            .restore();
+        assert_eq!(restored.intersection().time(), checkpoint.time());
+        assert_eq!(
+            restored.rollout(Phase::EastWest, &mut restored_rng),
+            carried_on
+        );
+    }
+
+    #[test]
+    fn rewinding_to_a_checkpoint_replays_from_it() {
+        let mut rng = Rng::seed_from_u64(1);
+        let mut simulation = simulation();
+        let checkpoint = Checkpoint::snapshot(&simulation, &rng);
+        let first = simulation.rollout(Phase::NorthSouth, &mut rng);
+        let (mut rewound, mut rng) = checkpoint.restore();
+        assert_eq!(rewound.rollout(Phase::NorthSouth, &mut rng), first);
+    }
+
+    #[test]
+    fn checkpoint_of_another_version_is_refused() {
+        let checkpoint = Checkpoint::snapshot(&simulation(), &Rng::default());
+        let mut value = serde_json::to_value(&checkpoint).unwrap();
+        value["version"] = (VERSION + 1).into();
+        let error = Checkpoint::<Actuated>::read(value.to_string().as_bytes())
+            .err()
+            .unwrap();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidData);
+    }
+}
@@ -0,0 +1,678 @@
+//! An optional continuous world. Each vehicle has a real-valued position, speed and acceleration,
+//! updated by a car-following model, and crashes are overlapping bodies rather than shared cells.
+//! It uses the same geometry, signal and controllers as the cell-based `Simulation`, and shares its
+//! state as the cell model sees it, so it can stand in as the higher-fidelity world the coarse
+//! simulation is checked against.
+//!
+//! Cell `p` of an approach is the interval `[p, p + 1)`, so the stop line is at `stop_line + 1`
+//! and the `k`th zone of a path is `[stop_line + 1 + k, stop_line + 2 + k)`.
+use crate::traffic::car::{Car, CarId, CarPos, VehicleClass};
+use crate::traffic::geometry::{Geometry, LaneId, Zone};
+use crate::traffic::intersection::{Intersection, IntersectionBuilder};
+use crate::traffic::kinematics::CarFollowing;
+use crate::traffic::light::Light;
+use crate::traffic::movement::Movement;
+use crate::traffic::observation::Observation;
+use crate::traffic::signal::{Signal, SignalState};
+use crate::traffic::simulation::{Simulation, Traffic};
+use std::collections::{HashMap, HashSet};
+
+static VEHICLE_LENGTH: f64 = 0.5;
+static SUBSTEPS: u32 = 4;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Vehicle {
+    pub id: CarId,
+    pub light: Light,
+    pub lane: LaneId,
+    pub movement: Movement,
+    pub class: VehicleClass,
+    /// Where the front bumper is, in cells from the start of the approach.
+    pub position: f64,
+    pub speed: f64,
+    pub acceleration: f64,
+}
+
+impl Vehicle {
+    /// Where the back bumper is, for a car `length` long.
+    fn rear(&self, length: f64) -> f64 {
+        self.position - length * self.class.length_factor()
+    }
+
+    /// Whether the front bumper is still short of the stop line.
+    fn before_stop_line(&self, geometry: &Geometry) -> bool {
+        self.position < (geometry.stop_line(&self.light) + 1) as f64
+    }
+
+    /// Past the box the lane splits into one exit road per movement.
+    fn on_exit_road(&self, length: f64, geometry: &Geometry) -> bool {
+        let last_in_box = geometry.last_in_box(&self.light, self.lane, self.movement);
+        self.rear(length) >= (last_in_box + 1) as f64
+    }
+
+    /// The zones some part of the body is over.
+    fn zones(&self, length: f64, geometry: &Geometry) -> Vec<Zone> {
+        let entry = (geometry.stop_line(&self.light) + 1) as f64;
+        geometry
+            .path(&self.light, self.lane, self.movement)
+            .iter()
+            .enumerate()
+            .filter(|(k, _)| {
+                let start = entry + *k as f64;
+                self.position > start && self.rear(length) < start + 1.0
+            })
+            .map(|(_, zone)| *zone)
+            .collect()
+    }
+
+    /// The cell the front bumper is in, as the cell-based model would see it.
+    fn as_car(&self) -> Car {
+        let mut car = Car::new(self.id, self.light.clone(), self.lane, self.movement);
+        car.class = self.class;
+        car.position = self.position.max(0.0).floor() as CarPos;
+        car
+    }
+}
+
+/// Vehicles moved by a car-following model, kept in step with the cell model's picture of them.
+/// That picture also holds the signal, pedestrians, lifecycles and running totals, so they work as
+/// they do for the cell model, and it is what the intersection shares as its state.
+#[derive(Clone)]
+pub struct ContinuousIntersection<F: CarFollowing> {
+    cells: Intersection,
+    vehicles: Vec<Vehicle>,
+    model: F,
+    vehicle_length: f64,
+    substeps: u32,
+}
+
+pub struct ContinuousIntersectionBuilder<F: CarFollowing> {
+    cells: IntersectionBuilder,
+    model: F,
+    vehicle_length: f64,
+    substeps: u32,
+}
+
+impl<F: CarFollowing + Default> Default for ContinuousIntersectionBuilder<F> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<F: CarFollowing + Default> ContinuousIntersectionBuilder<F> {
+    pub fn new() -> Self {
+        ContinuousIntersectionBuilder {
+            cells: IntersectionBuilder::new(),
+            model: F::default(),
+            vehicle_length: VEHICLE_LENGTH,
+            substeps: SUBSTEPS,
+        }
+    }
+}
+
+impl<F: CarFollowing> ContinuousIntersectionBuilder<F> {
+    pub fn with_geometry(&mut self, geometry: Geometry) -> &mut Self {
+        self.cells.with_geometry(geometry);
+        self
+    }
+
+    pub fn with_car_following(&mut self, model: F) -> &mut Self {
+        self.model = model;
+        self
+    }
+
+    /// A car's length bumper to bumper, in cells, which trucks and buses exceed by their class's
+    /// `length_factor`. Has to stay under one cell for each class for a queue to fit the cell
+    /// model's.
+    pub fn with_vehicle_length(&mut self, vehicle_length: f64) -> &mut Self {
+        self.vehicle_length = vehicle_length;
+        self
+    }
+
+    /// Kinematic updates per drive step.
+    pub fn with_substeps(&mut self, substeps: u32) -> &mut Self {
+        self.substeps = substeps.max(1);
+        self
+    }
+
+    pub fn with_min_green(&mut self, min_green: u32) -> &mut Self {
+        self.cells.with_min_green(min_green);
+        self
+    }
+
+    pub fn with_yellow(&mut self, yellow: u32) -> &mut Self {
+        self.cells.with_yellow(yellow);
+        self
+    }
+
+    pub fn with_all_red(&mut self, all_red: u32) -> &mut Self {
+        self.cells.with_all_red(all_red);
+        self
+    }
+
+    /// Drive steps a pedestrian takes to cross a leg.
+    pub fn with_crossing_steps(&mut self, crossing_steps: u32) -> &mut Self {
+        self.cells.with_crossing_steps(crossing_steps);
+        self
+    }
+
+    pub fn build(&self) -> ContinuousIntersection<F> {
+        ContinuousIntersection {
+            cells: self.cells.build(),
+            vehicles: Vec::new(),
+            model: self.model.clone(),
+            vehicle_length: self.vehicle_length,
+            substeps: self.substeps,
+        }
+    }
+}
+
+impl<F: CarFollowing> ContinuousIntersection<F> {
+    pub fn vehicles(&self) -> &[Vehicle] {
+        &self.vehicles
+    }
+    pub fn num_crashes(&self) -> u32 {
+        self.cells.num_crashes()
+    }
+    pub fn total_throughput(&self) -> u32 {
+        self.cells.total_throughput()
+    }
+    pub fn time(&self) -> u32 {
+        self.cells.time()
+    }
+    pub fn geometry(&self) -> &Geometry {
+        self.cells.geometry()
+    }
+    pub fn signal(&self) -> &Signal {
+        self.cells.signal()
+    }
+
+    /// The vehicles as the cell-based model would see them, front bumpers rounded down to a cell.
+    pub fn observe(&self) -> Observation {
+        self.cells.observe()
+    }
+
+    /// Whether a new vehicle has room to enter `lane` at the start of the approach.
+    fn is_entrance_free(&self, light: &Light, lane: LaneId) -> bool {
+        let room = self.vehicle_length + self.model.standstill_gap();
+        !self.vehicles.iter().any(|vehicle| {
+            &vehicle.light == light
+                && vehicle.lane == lane
+                && vehicle.rear(self.vehicle_length) < room
+        })
+    }
+
+    /// Of the lanes `movement` may use whose entrance is free, the one with fewest vehicles.
+    pub fn assign_lane(&self, light: &Light, movement: Movement) -> Option<LaneId> {
+        self.geometry()
+            .lanes(light)
+            .iter()
+            .enumerate()
+            .filter(|(lane, allowed)| {
+                allowed.allows(movement) && self.is_entrance_free(light, *lane)
+            })
+            .min_by_key(|(lane, _)| {
+                self.vehicles
+                    .iter()
+                    .filter(|vehicle| &vehicle.light == light && vehicle.lane == *lane)
+                    .count()
+            })
+            .map(|(lane, _)| lane)
+    }
+
+    /// None if every lane the vehicle may use is backed up to the entrance.
+    pub fn spawn_car(&mut self, light: Light, movement: Movement) -> Option<CarId> {
+        let lane = self.assign_lane(&light, movement)?;
+        Some(self.spawn_vehicle_in_lane(light, lane, movement, VehicleClass::default()))
+    }
+
+    /// A vehicle enters at the start of the approach at its desired speed, so an unhindered one
+    /// keeps pace with the cell model.
+    pub(crate) fn spawn_vehicle_in_lane(
+        &mut self,
+        light: Light,
+        lane: LaneId,
+        movement: Movement,
+        class: VehicleClass,
+    ) -> CarId {
+        let id = self
+            .cells
+            .spawn_vehicle_in_lane(light.clone(), lane, movement, class);
+        self.vehicles.push(Vehicle {
+            id,
+            light,
+            lane,
+            movement,
+            class,
+            position: 0.0,
+            speed: self.model.desired_speed(),
+            acceleration: 0.0,
+        });
+        id
+    }
+
+    /// A vehicle filling the back of `car`'s cell, standing if the car is and otherwise moving at
+    /// its desired speed.
+    fn vehicle_in_cell(&self, car: &Car) -> Vehicle {
+        Vehicle {
+            id: car.id,
+            light: car.light.clone(),
+            lane: car.lane,
+            movement: car.movement,
+            class: car.class,
+            position: car.position as f64 + self.vehicle_length * car.class.length_factor(),
+            speed: if car.standing {
+                0.0
+            } else {
+                self.model.desired_speed()
+            },
+            acceleration: 0.0,
+        }
+    }
+
+    /// Take on the traffic in `cells`. Vehicles still in the cell `cells` has them in keep their
+    /// speed and where they are within it; the others are put in their cell afresh.
+    pub(crate) fn sync_from_cells(&mut self, cells: &Intersection) {
+        let vehicles = std::mem::take(&mut self.vehicles);
+        self.vehicles = cells
+            .cars
+            .iter()
+            .map(|car| {
+                vehicles
+                    .iter()
+                    .find(|vehicle| {
+                        let seen = vehicle.as_car();
+                        (
+                            seen.id,
+                            &seen.light,
+                            seen.lane,
+                            seen.movement,
+                            seen.position,
+                        ) == (car.id, &car.light, car.lane, car.movement, car.position)
+                    })
+                    .cloned()
+                    .unwrap_or_else(|| self.vehicle_in_cell(car))
+            })
+            .collect();
+        self.cells = cells.clone();
+    }
+
+    /// The gap to, and speed of, whatever `vehicle` has to follow: the nearest vehicle ahead that
+    /// shares its road, or a stopped leader at the stop line if it ought to stop there, or at a
+    /// crosswalk that is in use.
+    fn leader(&self, vehicle: &Vehicle, crosswalks_in_use: &HashSet<Light>) -> (f64, f64) {
+        let length = self.vehicle_length;
+        let geometry = self.geometry();
+        let mut leader = (f64::INFINITY, 0.0);
+        for other in self.vehicles.iter() {
+            let shares_road = other.light == vehicle.light
+                && other.lane == vehicle.lane
+                && (other.movement == vehicle.movement || !other.on_exit_road(length, geometry));
+            if other.id != vehicle.id && shares_road && other.position > vehicle.position {
+                let gap = other.rear(length) - vehicle.position;
+                if gap < leader.0 {
+                    leader = (gap, other.speed);
+                }
+            }
+        }
+        if vehicle.before_stop_line(geometry) && self.stops_for_signal(vehicle) {
+            let gap = (geometry.stop_line(&vehicle.light) + 1) as f64 - vehicle.position;
+            if gap < leader.0 {
+                leader = (gap, 0.0);
+            }
+        }
+        if let Some(gap) = self.gap_to_crosswalk_ahead(vehicle, crosswalks_in_use) {
+            if gap < leader.0 {
+                leader = (gap, 0.0);
+            }
+        }
+        leader
+    }
+
+    /// How far the front bumper is from the next cell if that is a crosswalk in use, so the
+    /// vehicle waits in front of it as a car in the cell model does.
+    fn gap_to_crosswalk_ahead(
+        &self,
+        vehicle: &Vehicle,
+        crosswalks_in_use: &HashSet<Light>,
+    ) -> Option<f64> {
+        let car = vehicle.as_car();
+        let leg = car.crosswalk_ahead(self.geometry())?;
+        crosswalks_in_use
+            .contains(&leg)
+            .then(|| (car.position + 1) as f64 - vehicle.position)
+    }
+
+    /// Red means stop. On yellow a driver only stops if they can do so comfortably.
+    fn stops_for_signal(&self, vehicle: &Vehicle) -> bool {
+        let geometry = self.geometry();
+        match self.signal().state_of(&vehicle.light, vehicle.movement) {
+            SignalState::Green => false,
+            SignalState::Red => true,
+            SignalState::Yellow => {
+                let to_line = (geometry.stop_line(&vehicle.light) + 1) as f64 - vehicle.position;
+                let stopping =
+                    vehicle.speed.powi(2) / (2.0 * self.model.comfortable_deceleration());
+                stopping <= to_line
+            }
+        }
+    }
+
+    /// One kinematic update of every vehicle, leaders and followers alike seeing the old state.
+    fn substep(&mut self, dt: f64, crosswalks_in_use: &HashSet<Light>) {
+        let next_speeds: Vec<f64> = self
+            .vehicles
+            .iter()
+            .map(|vehicle| {
+                let (gap, leader_speed) = self.leader(vehicle, crosswalks_in_use);
+                self.model.next_speed(vehicle.speed, gap, leader_speed, dt)
+            })
+            .collect();
+        for (vehicle, next_speed) in self.vehicles.iter_mut().zip(next_speeds) {
+            vehicle.acceleration = (next_speed - vehicle.speed) / dt;
+            vehicle.position += (vehicle.speed + next_speed) / 2.0 * dt;
+            vehicle.speed = next_speed;
+        }
+    }
+
+    /// Move the vehicles through a drive step's substeps, then bring the cells up to date with
+    /// where they ended up and what crashed on the way.
+    pub(crate) fn advance(&mut self) {
+        let dt = 1.0 / self.substeps as f64;
+        let crosswalks_in_use = self.cells.crosswalks_in_use();
+        let mut crash_pairs = Vec::new();
+        for _ in 0..self.substeps {
+            self.substep(dt, &crosswalks_in_use);
+            crash_pairs.extend(self.update_crashes());
+        }
+        let positions: HashMap<CarId, CarPos> = self
+            .vehicles
+            .iter()
+            .map(|vehicle| (vehicle.id, vehicle.as_car().position))
+            .collect();
+        self.cells.advance_to(&positions, crash_pairs);
+        let on_road: HashSet<CarId> = self.cells.cars.iter().map(|car| car.id).collect();
+        self.vehicles
+            .retain(|vehicle| on_road.contains(&vehicle.id));
+    }
+
+    /// Two vehicles crash when their bodies overlap: over the same zone if they come from
+    /// different lanes, or anywhere on a road they share. Returns the pairs, lower id first, having
+    /// taken the vehicles in them off the road.
+    fn update_crashes(&mut self) -> Vec<(CarId, CarId)> {
+        let length = self.vehicle_length;
+        let geometry = self.geometry();
+        let mut crash_pairs = HashSet::new();
+        for (i, a) in self.vehicles.iter().enumerate() {
+            let zones_a = a.zones(length, geometry);
+            for b in self.vehicles.iter().skip(i + 1) {
+                let same_lane = a.light == b.light && a.lane == b.lane;
+                let crashed = if same_lane {
+                    let shares_road = a.movement == b.movement
+                        || !(a.on_exit_road(length, geometry) || b.on_exit_road(length, geometry));
+                    shares_road && a.position > b.rear(length) && b.position > a.rear(length)
+                } else {
+                    b.zones(length, geometry)
+                        .iter()
+                        .any(|zone| zones_a.contains(zone))
+                };
+                if crashed {
+                    crash_pairs.insert((a.id.min(b.id), a.id.max(b.id)));
+                }
+            }
+        }
+        let mut crash_pairs: Vec<(CarId, CarId)> = crash_pairs.into_iter().collect();
+        crash_pairs.sort();
+        let crashed_car_ids: HashSet<CarId> = crash_pairs
+            .iter()
+            .flat_map(|&(id1, id2)| vec![id1, id2])
+            .collect();
+        self.vehicles
+            .retain(|vehicle| !crashed_car_ids.contains(&vehicle.id));
+        crash_pairs
+    }
+}
+
+impl<F: CarFollowing> Traffic for ContinuousIntersection<F> {
+    fn cells(&self) -> &Intersection {
+        &self.cells
+    }
+    fn cells_mut(&mut self) -> &mut Intersection {
+        &mut self.cells
+    }
+    fn sync_from_cells(&mut self, cells: &Intersection) {
+        ContinuousIntersection::sync_from_cells(self, cells)
+    }
+    fn assign_lane(&self, light: &Light, movement: Movement) -> Option<LaneId> {
+        ContinuousIntersection::assign_lane(self, light, movement)
+    }
+    fn spawn_vehicle_in_lane(
+        &mut self,
+        light: Light,
+        lane: LaneId,
+        movement: Movement,
+        class: VehicleClass,
+    ) -> CarId {
+        ContinuousIntersection::spawn_vehicle_in_lane(self, light, lane, movement, class)
+    }
+    fn advance(&mut self) {
+        ContinuousIntersection::advance(self)
+    }
+}
+
+/// The continuous counterpart of the cell-based `Simulation`: the same spawning, controller and
+/// decision periods around a `ContinuousIntersection`. Build one with
+/// `SimulationBuilder::with_intersection`.
+pub type ContinuousSimulation<C, F> = Simulation<C, ContinuousIntersection<F>>;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::cfg;
+    use crate::data::prng::Rng;
+    use crate::gatekeeper::fidelity::Fidelity;
+    use crate::gatekeeper::model::Model;
+    use crate::gatekeeper::{Gatekeeper, GatekeeperBuilder};
+    use crate::logic::syntax::Prop;
+    use crate::traffic::demand::{Demand, Proportions};
+    use crate::traffic::kinematics::{Gipps, Idm};
+    use crate::traffic::light::Phase;
+    use crate::traffic::simulation::{Random, SimulationBuilder};
+    use rand::SeedableRng;
+
+    /// Steps until the first vehicle leaves the road, if one does within `limit`.
+    fn travel_time<F: CarFollowing>(
+        intersection: &mut ContinuousIntersection<F>,
+        limit: u32,
+    ) -> Option<u32> {
+        (1..=limit).find(|_| {
+            intersection.advance();
+            intersection.total_throughput() > 0
+        })
+    }
+
+    #[test]
+    fn free_flowing_car_matches_cell_model() {
+        let road_length: u32 = cfg().get("road_length").unwrap();
+        let mut cells = IntersectionBuilder::new().build();
+        cells.request_phase(Phase::NorthSouth);
+        cells.spawn_car(Light::N);
+        let cell_time = (1..=2 * road_length).find(|_| {
+            cells.advance();
+            cells.total_throughput() > 0
+        });
+        let mut idm = ContinuousIntersectionBuilder::<Idm>::new().build();
+        idm.cells.request_phase(Phase::NorthSouth);
+        idm.spawn_car(Light::N, Movement::Through);
+        let mut gipps = ContinuousIntersectionBuilder::<Gipps>::new().build();
+        gipps.cells.request_phase(Phase::NorthSouth);
+        gipps.spawn_car(Light::N, Movement::Through);
+        assert_eq!(travel_time(&mut idm, 2 * road_length), cell_time);
+        assert_eq!(travel_time(&mut gipps, 2 * road_length), cell_time);
+    }
+
+    #[test]
+    fn red_light_stops_car_before_stop_line() {
+        let light_coord: u32 = cfg().get("light_coord").unwrap();
+        let mut intersection = ContinuousIntersectionBuilder::<Idm>::new().build();
+        intersection.spawn_car(Light::N, Movement::Through);
+        intersection.spawn_car(Light::N, Movement::Through);
+        for _ in 0..30 {
+            intersection.advance();
+            if intersection.vehicles().len() < 2 {
+                intersection.spawn_car(Light::N, Movement::Through);
+            }
+        }
+        let front = intersection.vehicles()[0].position;
+        assert!(front < (light_coord + 1) as f64 && front > light_coord as f64);
+        assert!(intersection.vehicles()[0].speed < 1e-3);
+        assert_eq!(intersection.observe().queue_length(&Light::N), 2);
+        assert_eq!(intersection.num_crashes(), 0);
+    }
+
+    #[test]
+    fn car_queues_behind_the_whole_length_of_a_truck() {
+        let mut intersection = ContinuousIntersectionBuilder::<Idm>::new().build();
+        intersection.spawn_vehicle_in_lane(Light::N, 0, Movement::Through, VehicleClass::Truck);
+        for _ in 0..30 {
+            intersection.advance();
+            if intersection.vehicles().len() < 2 {
+                intersection.spawn_car(Light::N, Movement::Through);
+            }
+        }
+        let (truck, car) = (&intersection.vehicles()[0], &intersection.vehicles()[1]);
+        assert!(car.position <= truck.position - VEHICLE_LENGTH * 1.6);
+        assert_eq!(intersection.num_crashes(), 0);
+    }
+
+    #[test]
+    fn perpendicular_cars_overlapping_in_a_zone_crash() {
+        let mut intersection = ContinuousIntersectionBuilder::<Idm>::new()
+            .with_yellow(0)
+            .build();
+        intersection.cells.signal.force_green(Light::N);
+        intersection.cells.signal.force_green(Light::W);
+        intersection.spawn_car(Light::N, Movement::Through);
+        intersection.spawn_car(Light::W, Movement::Through);
+        for _ in 0..10 {
+            intersection.advance();
+        }
+        assert_eq!(intersection.num_crashes(), 1);
+        assert!(intersection.vehicles().is_empty());
+    }
+
+    #[test]
+    fn continuous_simulation_is_a_model() {
+        let mut prng = Rng::seed_from_u64(0);
+        let simulation: ContinuousSimulation<Random, Idm> = SimulationBuilder::new()
+            .with_intersection(ContinuousIntersectionBuilder::new().build())
+            .with_max_cars(16)
+            .with_drive_steps_per_lightswitch(8)
+            .with_max_steps(4)
+            .build();
+        let mut world = simulation.clone();
+        world.run(&mut prng);
+        let trajectory = simulation.rollout_from(&world.snapshot(), Phase::EastWest, &mut prng);
+        assert_eq!(trajectory.len(), 4);
+        assert_eq!(simulation.intersection().time(), 0);
+    }
+
+    /// Cars going straight on from every approach, which never crash in the cell model while the
+    /// signal has an all-red clearance.
+    fn through_traffic<I: Traffic>(intersection: I, max_steps: u32) -> Simulation<Random, I> {
+        Light::all()
+            .into_iter()
+            .fold(
+                SimulationBuilder::new()
+                    .with_intersection(intersection)
+                    .with_demand(Demand::uniform(0.3)),
+                |builder, light| {
+                    builder.with_turning_proportions(light, Proportions::only(Movement::Through))
+                },
+            )
+            .with_max_cars(16)
+            .with_drive_steps_per_lightswitch(8)
+            .with_max_steps(max_steps)
+            .build()
+    }
+
+    #[test]
+    fn syncing_from_its_own_snapshot_keeps_every_vehicle() {
+        let mut prng = Rng::seed_from_u64(3);
+        let mut simulation =
+            through_traffic(ContinuousIntersectionBuilder::<Idm>::new().build(), 4);
+        simulation.run(&mut prng);
+        let vehicles = simulation.intersection().vehicles().to_vec();
+        assert!(!vehicles.is_empty());
+        simulation.sync_from(&simulation.snapshot());
+        assert_eq!(simulation.intersection().vehicles(), vehicles.as_slice());
+    }
+
+    #[test]
+    fn vehicle_synced_from_cells_sits_in_its_cell() {
+        let light_coord: u32 = cfg().get("light_coord").unwrap();
+        let mut cells = IntersectionBuilder::new().build();
+        cells.spawn_car(Light::N);
+        for _ in 0..(light_coord + 1) {
+            cells.advance();
+        }
+        let mut intersection = ContinuousIntersectionBuilder::<Idm>::new().build();
+        intersection.sync_from_cells(&cells);
+        let vehicle = &intersection.vehicles()[0];
+        assert_eq!(vehicle.as_car().position, light_coord);
+        assert_eq!(vehicle.speed, 0.0);
+        assert_eq!(intersection.observe().queue_length(&Light::N), 1);
+    }
+
+    #[test]
+    fn turning_vehicle_waits_for_pedestrian_on_walk() {
+        let mut intersection = ContinuousIntersectionBuilder::<Idm>::new().build();
+        intersection.cells.request_phase(Phase::NorthSouth);
+        intersection.spawn_car(Light::N, Movement::Left);
+        let exit = intersection
+            .geometry()
+            .last_in_box(&Light::N, 0, Movement::Left)
+            + 1;
+        while intersection.vehicles()[0].position + 3.0 < exit as f64 {
+            intersection.advance();
+        }
+        intersection.cells.spawn_pedestrian(Light::E);
+        while intersection.cells.pedestrians_crossed() == 0 {
+            intersection.advance();
+            assert!(intersection.vehicles()[0].position < exit as f64);
+        }
+        assert!(travel_time(&mut intersection, 8).is_some());
+        assert_eq!(intersection.cells.num_pedestrian_incidents(), 0);
+    }
+
+    #[test]
+    fn continuous_world_keeps_the_cell_simulation_predictive() {
+        let cells = through_traffic(IntersectionBuilder::new().with_all_red(1).build(), 2);
+        let world = through_traffic(
+            ContinuousIntersectionBuilder::<Idm>::new()
+                .with_all_red(1)
+                .build(),
+            2,
+        );
+        let mut gatekeeper: Gatekeeper<
+            Random,
+            Simulation<Random>,
+            ContinuousSimulation<Random, Idm>,
+        > = GatekeeperBuilder::new(cells, world)
+            .with_spec(Prop::var)
+            .with_fidelity(Fidelity::new(0.1, 4))
+            .with_seed(0)
+            .build();
+        let episode = gatekeeper.run_episode(8);
+        assert!(
+            episode
+                .trajectory()
+                .iter()
+                .map(|entry| entry.num_cars_throughput())
+                .sum::<u32>()
+                > 0
+        );
+        assert_eq!(gatekeeper.fidelity().num_verdicts(), 8);
+        assert!(gatekeeper.fidelity().is_predictive());
+    }
+}
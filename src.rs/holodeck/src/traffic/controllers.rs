@@ -0,0 +1,388 @@
+//! Classical signal controllers, as baselines for the gatekeeper. Unlike `Random` they choose
+//! phases from the queues they observe.
+use crate::data::prng::Rng;
+use crate::gatekeeper::model::Controller;
+use crate::traffic::light::Phase;
+use crate::traffic::observation::Observation;
+use serde::{Deserialize, Serialize};
+
+/// The phase scoring highest under `score`, keeping the current phase on ties so that
+/// the lights don't flicker between equally good phases.
+fn best_phase<F>(observation: &Observation, score: F) -> Phase
+where
+    F: Fn(Phase) -> i64,
+{
+    // With nothing green there is no current phase worth keeping.
+    let current = observation
+        .phase()
+        .filter(|phase| phase != &Phase::AllRed)
+        .unwrap_or(Phase::NorthSouth);
+    Phase::all().into_iter().fold(current, |best, phase| {
+        if score(phase) > score(best) {
+            phase
+        } else {
+            best
+        }
+    })
+}
+
+/// Cars waiting on the movements `phase` gives green to.
+fn queue(observation: &Observation, phase: Phase) -> u32 {
+    phase
+        .movements()
+        .iter()
+        .map(|(light, movement)| observation.queue_length_of(light, *movement))
+        .sum()
+}
+
+/// Cycles through the phases regardless of traffic, holding each for its split, counted in
+/// decision periods.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct FixedTime {
+    splits: Vec<(Phase, u32)>,
+    index: usize,
+    periods_in_phase: u32,
+}
+
+impl FixedTime {
+    pub fn new(splits: Vec<(Phase, u32)>) -> Self {
+        assert!(
+            splits.iter().any(|(_, split)| *split > 0),
+            "a cycle needs at least one nonzero split"
+        );
+        FixedTime {
+            splits,
+            index: 0,
+            periods_in_phase: 0,
+        }
+    }
+    pub fn splits(&self) -> &[(Phase, u32)] {
+        &self.splits
+    }
+}
+
+/// One period of north-south, then one of east-west.
+impl Default for FixedTime {
+    fn default() -> Self {
+        FixedTime::new(vec![(Phase::NorthSouth, 1), (Phase::EastWest, 1)])
+    }
+}
+
+impl Controller for FixedTime {
+    type Observation = Observation;
+    type Action = Phase;
+
+    fn select_action(&mut self, _observation: &Observation, _rng: &mut Rng) -> Phase {
+        while self.periods_in_phase >= self.splits[self.index].1 {
+            self.index = (self.index + 1) % self.splits.len();
+            self.periods_in_phase = 0;
+        }
+        self.periods_in_phase += 1;
+        self.splits[self.index].0
+    }
+}
+
+/// Holds the green phase for at least `min_green` periods, then keeps extending it while cars are
+/// still queued on it, up to `max_green`, then moves on to the next phase in its sequence that has
+/// cars waiting. Rests in green when nobody is waiting on any other phase.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Actuated {
+    min_green: u32,
+    max_green: u32,
+    sequence: Vec<Phase>,
+    index: usize,
+    periods_in_phase: u32,
+}
+
+impl Actuated {
+    pub fn new(min_green: u32, max_green: u32) -> Self {
+        assert!(
+            0 < min_green && min_green <= max_green,
+            "need 0 < min_green <= max_green"
+        );
+        Actuated {
+            min_green,
+            max_green,
+            sequence: vec![Phase::NorthSouth, Phase::EastWest],
+            index: 0,
+            periods_in_phase: 0,
+        }
+    }
+    /// The phases to cycle through, by default just the two through phases.
+    pub fn with_sequence(mut self, sequence: Vec<Phase>) -> Self {
+        assert!(!sequence.is_empty(), "need at least one phase");
+        self.sequence = sequence;
+        self.index = 0;
+        self
+    }
+    pub fn min_green(&self) -> u32 {
+        self.min_green
+    }
+    pub fn max_green(&self) -> u32 {
+        self.max_green
+    }
+}
+
+impl Default for Actuated {
+    fn default() -> Self {
+        Actuated::new(1, 4)
+    }
+}
+
+impl Controller for Actuated {
+    type Observation = Observation;
+    type Action = Phase;
+
+    fn select_action(&mut self, observation: &Observation, _rng: &mut Rng) -> Phase {
+        let phase = self.sequence[self.index];
+        let waiting = (1..self.sequence.len())
+            .map(|k| (self.index + k) % self.sequence.len())
+            .find(|&i| self.sequence[i] != phase && queue(observation, self.sequence[i]) > 0);
+        let maxed_out = self.periods_in_phase >= self.max_green;
+        let gapped_out = self.periods_in_phase >= self.min_green && queue(observation, phase) == 0;
+        if let Some(next) = waiting.filter(|_| maxed_out || gapped_out) {
+            self.index = next;
+            self.periods_in_phase = 0;
+        }
+        self.periods_in_phase += 1;
+        self.sequence[self.index]
+    }
+}
+
+/// Gives green to the phase with the most pressure: cars queued for its movements minus cars that
+/// made those movements and are still on the road past the stop line.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MaxPressure;
+
+impl Controller for MaxPressure {
+    type Observation = Observation;
+    type Action = Phase;
+
+    fn select_action(&mut self, observation: &Observation, _rng: &mut Rng) -> Phase {
+        let pressure = |phase: Phase| {
+            phase
+                .movements()
+                .iter()
+                .map(|(light, movement)| {
+                    observation.queue_length_of(light, *movement) as i64
+                        - observation.downstream_length_of(light, *movement) as i64
+                })
+                .sum()
+        };
+        best_phase(observation, pressure)
+    }
+}
+
+/// Gives green to the phase serving the single longest queue of cars waiting to make one movement.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct LongestQueueFirst;
+
+impl Controller for LongestQueueFirst {
+    type Observation = Observation;
+    type Action = Phase;
+
+    fn select_action(&mut self, observation: &Observation, _rng: &mut Rng) -> Phase {
+        let longest = |phase: Phase| {
+            phase
+                .movements()
+                .iter()
+                .map(|(light, movement)| observation.queue_length_of(light, *movement) as i64)
+                .max()
+                .unwrap_or(0)
+        };
+        best_phase(observation, longest)
+    }
+}
+
+/// Gives an approaching emergency vehicle's movement a green, and leaves every other decision to
+/// the controller it wraps.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct Preempting<C> {
+    inner: C,
+}
+
+impl<C> Preempting<C> {
+    pub fn new(inner: C) -> Self {
+        Preempting { inner }
+    }
+    pub fn inner(&self) -> &C {
+        &self.inner
+    }
+}
+
+impl<C: Controller<Observation = Observation, Action = Phase>> Controller for Preempting<C> {
+    type Observation = Observation;
+    type Action = Phase;
+
+    fn select_action(&mut self, observation: &Observation, rng: &mut Rng) -> Phase {
+        match observation.preemption() {
+            Some((light, movement)) => Phase::serving(&light, movement),
+            None => self.inner.select_action(observation, rng),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::cfg;
+    use crate::traffic::car::VehicleClass;
+    use crate::traffic::intersection::{Intersection, IntersectionBuilder};
+    use crate::traffic::light::Light;
+    use crate::traffic::movement::Movement;
+    use rand::SeedableRng;
+
+    /// An intersection with `n` cars queued on each of `queued`.
+    fn queued(queued: &[(Light, u32)]) -> Intersection {
+        let mut intersection = IntersectionBuilder::new().build();
+        for (light, n) in queued {
+            for _ in 0..*n {
+                intersection.spawn_car(light.clone());
+            }
+        }
+        intersection
+    }
+
+    fn phases<C: Controller<Observation = Observation, Action = Phase>>(
+        controller: &mut C,
+        intersection: &Intersection,
+        n: usize,
+    ) -> Vec<Phase> {
+        let mut prng = Rng::seed_from_u64(0);
+        (0..n)
+            .map(|_| controller.select_action(&intersection.observe(), &mut prng))
+            .collect()
+    }
+
+    #[test]
+    fn fixed_time_follows_splits() {
+        let mut controller = FixedTime::new(vec![(Phase::NorthSouth, 2), (Phase::EastWest, 1)]);
+        use Phase::*;
+        assert_eq!(
+            phases(&mut controller, &queued(&[]), 6),
+            vec![NorthSouth, NorthSouth, EastWest, NorthSouth, NorthSouth, EastWest]
+        );
+    }
+
+    #[test]
+    fn fixed_time_skips_zero_splits() {
+        let mut controller = FixedTime::new(vec![(Phase::NorthSouth, 0), (Phase::EastWest, 1)]);
+        assert_eq!(
+            phases(&mut controller, &queued(&[]), 3),
+            vec![Phase::EastWest; 3]
+        );
+    }
+
+    #[test]
+    fn actuated_extends_green_up_to_max() {
+        let mut controller = Actuated::new(1, 3);
+        use Phase::*;
+        assert_eq!(
+            phases(&mut controller, &queued(&[(Light::N, 1), (Light::E, 1)]), 5),
+            vec![NorthSouth, NorthSouth, NorthSouth, EastWest, EastWest]
+        );
+    }
+
+    #[test]
+    fn actuated_gaps_out_after_min_green() {
+        let mut controller = Actuated::new(2, 8);
+        use Phase::*;
+        assert_eq!(
+            phases(&mut controller, &queued(&[(Light::E, 1)]), 4),
+            vec![NorthSouth, NorthSouth, EastWest, EastWest]
+        );
+    }
+
+    #[test]
+    fn actuated_skips_phases_without_demand() {
+        let mut controller = Actuated::new(1, 2).with_sequence(vec![
+            Phase::NorthSouthLeft,
+            Phase::NorthSouth,
+            Phase::EastWestLeft,
+            Phase::EastWest,
+        ]);
+        use Phase::*;
+        assert_eq!(
+            phases(&mut controller, &queued(&[(Light::E, 1)]), 3),
+            vec![NorthSouthLeft, EastWest, EastWest]
+        );
+    }
+
+    #[test]
+    fn actuated_rests_in_green_without_demand() {
+        let mut controller = Actuated::new(1, 2);
+        assert_eq!(
+            phases(&mut controller, &queued(&[(Light::N, 1)]), 4),
+            vec![Phase::NorthSouth; 4]
+        );
+    }
+
+    #[test]
+    fn max_pressure_discounts_cars_downstream() {
+        let light_coord: u32 = cfg().get("light_coord").unwrap();
+        let mut intersection = IntersectionBuilder::new().build();
+        intersection.request_phase(Phase::NorthSouth);
+        for _ in 0..3 {
+            intersection.spawn_car(Light::N);
+            intersection.advance();
+        }
+        for _ in 0..light_coord {
+            intersection.advance();
+        }
+        // Three cars past the stop line on N, two waiting on N and one on E.
+        intersection.spawn_car(Light::N);
+        intersection.spawn_car(Light::N);
+        intersection.spawn_car(Light::E);
+        let mut controller = MaxPressure;
+        assert_eq!(
+            phases(&mut controller, &intersection, 1),
+            vec![Phase::EastWest]
+        );
+        assert_eq!(
+            phases(&mut LongestQueueFirst, &intersection, 1),
+            vec![Phase::NorthSouth]
+        );
+    }
+
+    #[test]
+    fn longest_queue_first_follows_longest_queue() {
+        let intersection = queued(&[(Light::N, 1), (Light::S, 1), (Light::W, 3)]);
+        assert_eq!(
+            phases(&mut LongestQueueFirst, &intersection, 1),
+            vec![Phase::EastWest]
+        );
+        assert_eq!(
+            phases(&mut MaxPressure, &intersection, 1),
+            vec![Phase::EastWest]
+        );
+    }
+
+    #[test]
+    fn ties_keep_the_current_phase() {
+        let mut intersection = queued(&[(Light::N, 1), (Light::E, 1)]);
+        intersection.request_phase(Phase::EastWest);
+        assert_eq!(
+            phases(&mut LongestQueueFirst, &intersection, 1),
+            vec![Phase::EastWest]
+        );
+        assert_eq!(
+            phases(&mut MaxPressure, &intersection, 1),
+            vec![Phase::EastWest]
+        );
+    }
+
+    #[test]
+    fn preemption_overrides_the_wrapped_controller() {
+        let mut intersection = queued(&[(Light::N, 3)]);
+        let mut controller = Preempting::new(FixedTime::new(vec![(Phase::NorthSouth, 1)]));
+        assert_eq!(
+            phases(&mut controller, &intersection, 1),
+            vec![Phase::NorthSouth]
+        );
+        intersection.spawn_vehicle_in_lane(Light::E, 0, Movement::Through, VehicleClass::Emergency);
+        assert_eq!(
+            phases(&mut controller, &intersection, 2),
+            vec![Phase::EastWest; 2]
+        );
+    }
+}
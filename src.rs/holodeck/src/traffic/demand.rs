@@ -0,0 +1,288 @@
+//! How many cars arrive on each approach, when, where they are headed and what they are. Rates
+//! are per drive step and can follow a piecewise-constant profile over time, e.g. a rush hour.
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::Rng as _;
+
+use crate::data::prng::Rng;
+use crate::traffic::car::VehicleClass;
+use crate::traffic::light::Light;
+use crate::traffic::movement::Movement;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ArrivalProcess {
+    /// At most one arrival per step, with probability equal to the rate.
+    #[default]
+    Bernoulli,
+    /// A Poisson-distributed number of arrivals per step with the rate as its mean.
+    Poisson,
+}
+
+/// An arrival rate that is constant between breakpoints.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Profile {
+    /// `(from_step, rate)`, sorted by step. The first breakpoint is at step 0.
+    breakpoints: Vec<(u32, f64)>,
+    /// If set, the table starts over every `period` steps.
+    period: Option<u32>,
+}
+
+impl Profile {
+    pub fn constant(rate: f64) -> Self {
+        Profile::piecewise(vec![(0, rate)])
+    }
+
+    /// `rate` applies from `from_step` until the next breakpoint. Before the first breakpoint the
+    /// rate is zero.
+    pub fn piecewise(mut breakpoints: Vec<(u32, f64)>) -> Self {
+        assert!(
+            breakpoints.iter().all(|(_, rate)| *rate >= 0.0),
+            "arrival rates can't be negative"
+        );
+        breakpoints.sort_by_key(|(from_step, _)| *from_step);
+        if breakpoints.first().map(|(from_step, _)| *from_step) != Some(0) {
+            breakpoints.insert(0, (0, 0.0));
+        }
+        Profile {
+            breakpoints,
+            period: None,
+        }
+    }
+
+    /// Repeat the table every `period` steps, e.g. one rush hour a day.
+    pub fn repeating(mut self, period: u32) -> Self {
+        assert!(period > 0, "a profile can't repeat every 0 steps");
+        self.period = Some(period);
+        self
+    }
+
+    pub fn rate_at(&self, time: u32) -> f64 {
+        let time = self.period.map_or(time, |period| time % period);
+        self.breakpoints
+            .iter()
+            .take_while(|(from_step, _)| *from_step <= time)
+            .last()
+            .map_or(0.0, |(_, rate)| *rate)
+    }
+}
+
+/// Pick one of a set of outcomes with the given relative weights.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Proportions<T> {
+    weighted: Vec<(T, f64)>,
+}
+
+impl<T: Clone> Proportions<T> {
+    pub fn new(weighted: Vec<(T, f64)>) -> Self {
+        assert!(
+            weighted.iter().all(|(_, weight)| *weight >= 0.0)
+                && weighted.iter().any(|(_, weight)| *weight > 0.0),
+            "proportions need a positive weight and no negative ones"
+        );
+        Proportions { weighted }
+    }
+
+    /// Just `outcome`, every time.
+    pub fn only(outcome: T) -> Self {
+        Proportions::new(vec![(outcome, 1.0)])
+    }
+
+    pub fn sample(&self, rng: &mut Rng) -> T {
+        let index = WeightedIndex::new(self.weighted.iter().map(|(_, weight)| *weight))
+            .expect("checked in new")
+            .sample(rng);
+        self.weighted[index].0.clone()
+    }
+}
+
+/// Demand on one approach.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct ApproachDemand {
+    process: ArrivalProcess,
+    profile: Profile,
+    turning: Proportions<Movement>,
+}
+
+impl ApproachDemand {
+    /// Cars arriving by `process` at rates following `profile`, turning every way equally often.
+    pub fn new(process: ArrivalProcess, profile: Profile) -> Self {
+        ApproachDemand {
+            process,
+            profile,
+            turning: Proportions::new(Movement::all().into_iter().map(|m| (m, 1.0)).collect()),
+        }
+    }
+
+    pub fn with_turning(mut self, turning: Proportions<Movement>) -> Self {
+        self.turning = turning;
+        self
+    }
+
+    pub fn process(&self) -> ArrivalProcess {
+        self.process
+    }
+    pub fn profile(&self) -> &Profile {
+        &self.profile
+    }
+    pub fn turning(&self) -> &Proportions<Movement> {
+        &self.turning
+    }
+
+    pub fn arrivals(&self, time: u32, rng: &mut Rng) -> u32 {
+        let rate = self.profile.rate_at(time);
+        match self.process {
+            ArrivalProcess::Bernoulli => rng.gen_bool(rate.min(1.0)) as u32,
+            ArrivalProcess::Poisson => poisson(rate, rng),
+        }
+    }
+}
+
+/// Knuth's method, which is fine for the handful of arrivals per step we expect.
+fn poisson(mean: f64, rng: &mut Rng) -> u32 {
+    let threshold = (-mean).exp();
+    let mut product: f64 = rng.gen();
+    let mut count = 0;
+    while product > threshold {
+        product *= rng.gen::<f64>();
+        count += 1;
+    }
+    count
+}
+
+/// Demand on every approach. An approach without an entry gets no traffic.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Demand {
+    approaches: HashMap<Light, ApproachDemand>,
+    classes: Proportions<VehicleClass>,
+}
+
+impl Default for Demand {
+    fn default() -> Self {
+        Demand {
+            approaches: HashMap::new(),
+            classes: Proportions::only(VehicleClass::default()),
+        }
+    }
+}
+
+impl Demand {
+    /// The same Bernoulli `rate` on every approach, turning every way equally often.
+    pub fn uniform(rate: f64) -> Self {
+        Light::all()
+            .into_iter()
+            .fold(Demand::default(), |demand, light| {
+                demand.with_approach(
+                    light,
+                    ApproachDemand::new(ArrivalProcess::Bernoulli, Profile::constant(rate)),
+                )
+            })
+    }
+
+    pub fn with_approach(mut self, light: Light, demand: ApproachDemand) -> Self {
+        self.approaches.insert(light, demand);
+        self
+    }
+
+    /// The mix of vehicles arriving, the same on every approach.
+    pub fn with_classes(mut self, classes: Proportions<VehicleClass>) -> Self {
+        self.classes = classes;
+        self
+    }
+
+    pub fn approach(&self, light: &Light) -> Option<&ApproachDemand> {
+        self.approaches.get(light)
+    }
+
+    /// Change how cars arrive on `light`, keeping where they are headed.
+    pub(crate) fn set_arrivals(&mut self, light: Light, process: ArrivalProcess, profile: Profile) {
+        let demand = self
+            .approaches
+            .entry(light)
+            .or_insert_with(|| ApproachDemand::new(process, profile.clone()));
+        demand.process = process;
+        demand.profile = profile;
+    }
+
+    /// Change where cars arriving on `light` are headed. Until it is given arrivals, the approach
+    /// gets no traffic.
+    pub(crate) fn set_turning(&mut self, light: Light, turning: Proportions<Movement>) {
+        self.approaches
+            .entry(light)
+            .or_insert_with(|| {
+                ApproachDemand::new(ArrivalProcess::default(), Profile::constant(0.0))
+            })
+            .turning = turning;
+    }
+
+    pub fn rate_at(&self, light: &Light, time: u32) -> f64 {
+        self.approach(light)
+            .map_or(0.0, |demand| demand.profile.rate_at(time))
+    }
+
+    /// The cars arriving at step `time`, by approach, with where they are headed and what they
+    /// are. Approaches are drawn in a fixed order so a seed always gives the same arrivals.
+    pub fn arrivals(&self, time: u32, rng: &mut Rng) -> Vec<(Light, Movement, VehicleClass)> {
+        let mut arrivals = Vec::new();
+        for light in Light::all() {
+            if let Some(demand) = self.approach(&light) {
+                for _ in 0..demand.arrivals(time, rng) {
+                    let movement = demand.turning.sample(rng);
+                    let class = self.classes.sample(rng);
+                    arrivals.push((light.clone(), movement, class));
+                }
+            }
+        }
+        arrivals
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rand::SeedableRng;
+
+    #[test]
+    fn piecewise_profile_holds_rate_between_breakpoints() {
+        let profile = Profile::piecewise(vec![(10, 0.8), (20, 0.1)]);
+        assert_eq!(profile.rate_at(0), 0.0);
+        assert_eq!(profile.rate_at(10), 0.8);
+        assert_eq!(profile.rate_at(19), 0.8);
+        assert_eq!(profile.rate_at(500), 0.1);
+        let daily = Profile::piecewise(vec![(0, 0.1), (5, 0.9)]).repeating(8);
+        assert_eq!(daily.rate_at(13), 0.9);
+        assert_eq!(daily.rate_at(17), 0.1);
+    }
+
+    #[test]
+    fn arrival_processes_have_the_right_mean() {
+        let mut rng = Rng::seed_from_u64(0);
+        let steps = 20_000;
+        for (process, rate) in [
+            (ArrivalProcess::Bernoulli, 0.3),
+            (ArrivalProcess::Poisson, 1.5),
+        ] {
+            let demand = ApproachDemand::new(process, Profile::constant(rate));
+            let total: u32 = (0..steps).map(|t| demand.arrivals(t, &mut rng)).sum();
+            let mean = total as f64 / steps as f64;
+            assert!((mean - rate).abs() < 0.05, "{:?}: {}", process, mean);
+        }
+    }
+
+    #[test]
+    fn arrivals_follow_turning_proportions_and_classes() {
+        let mut rng = Rng::seed_from_u64(1);
+        let demand = Demand::default()
+            .with_approach(
+                Light::E,
+                ApproachDemand::new(ArrivalProcess::Poisson, Profile::constant(2.0))
+                    .with_turning(Proportions::only(Movement::Left)),
+            )
+            .with_classes(Proportions::only(VehicleClass::Bus));
+        let arrivals: Vec<_> = (0..50).flat_map(|t| demand.arrivals(t, &mut rng)).collect();
+        assert!(!arrivals.is_empty());
+        assert!(arrivals
+            .iter()
+            .all(|arrival| arrival == &(Light::E, Movement::Left, VehicleClass::Bus)));
+    }
+}
@@ -0,0 +1,453 @@
+//! The layout of an intersection, as data: how long each approach road is, where its stop line
+//! is, its lanes, and which conflict zones inside the box each lane's movements drive through.
+//! Two cars crash when they are in the same zone in the same step.
+use crate::cfg::cfg;
+use crate::traffic::car::CarPos;
+use crate::traffic::light::Light;
+use crate::traffic::movement::Movement;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+
+/// A shared area inside the intersection that only one car can be in at a time.
+pub type Zone = u32;
+
+/// A lane's index on its approach, counting from the kerb.
+pub type LaneId = usize;
+
+/// A movement made from one lane of one approach.
+pub type LaneMovement = (Light, LaneId, Movement);
+
+/// The movements a lane may be used for, which is also the signal group its signal head follows.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lane {
+    movements: Vec<Movement>,
+}
+
+impl Lane {
+    pub fn new(movements: Vec<Movement>) -> Self {
+        assert!(!movements.is_empty(), "a lane has to lead somewhere");
+        Lane { movements }
+    }
+    pub fn movements(&self) -> &[Movement] {
+        &self.movements
+    }
+    pub fn allows(&self, movement: Movement) -> bool {
+        self.movements.contains(&movement)
+    }
+}
+
+/// A lane for every movement.
+impl Default for Lane {
+    fn default() -> Self {
+        Lane::new(Movement::all().to_vec())
+    }
+}
+
+/// One approach road, with positions counted from where its cars spawn.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Approach {
+    stop_line: CarPos,
+    length: CarPos,
+    lanes: Vec<Lane>,
+}
+
+impl Approach {
+    /// Cars wait at `stop_line` for a green and leave the road when they reach `length`. There is
+    /// one lane, for every movement.
+    pub fn new(stop_line: CarPos, length: CarPos) -> Self {
+        assert!(stop_line < length, "the stop line has to be on the road");
+        Approach {
+            stop_line,
+            length,
+            lanes: vec![Lane::default()],
+        }
+    }
+    /// Lanes in order from the kerb.
+    pub fn with_lanes(mut self, lanes: Vec<Lane>) -> Self {
+        assert!(!lanes.is_empty(), "an approach needs a lane");
+        self.lanes = lanes;
+        self
+    }
+    /// `n` lanes that all go straight on, where the kerb lane also turns right and the lane
+    /// nearest the centre also turns left.
+    pub fn with_num_lanes(self, n: usize) -> Self {
+        let lanes = (0..n)
+            .map(|lane| {
+                let mut movements = vec![Movement::Through];
+                if lane == 0 {
+                    movements.push(Movement::Right);
+                }
+                if lane + 1 == n {
+                    movements.push(Movement::Left);
+                }
+                Lane::new(movements)
+            })
+            .collect();
+        self.with_lanes(lanes)
+    }
+    pub fn stop_line(&self) -> CarPos {
+        self.stop_line
+    }
+    pub fn length(&self) -> CarPos {
+        self.length
+    }
+    pub fn lanes(&self) -> &[Lane] {
+        &self.lanes
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Geometry {
+    approaches: HashMap<Light, Approach>,
+    /// The zones a movement occupies in the steps after it crosses the stop line, one per step.
+    #[serde(with = "crate::data::pairs")]
+    paths: HashMap<LaneMovement, Vec<Zone>>,
+}
+
+/// Every approach has one lane and the stop line and length from the config.
+impl Default for Geometry {
+    fn default() -> Self {
+        GeometryBuilder::new().build()
+    }
+}
+
+/// Lay the box out as a grid with a cell where each lane crosses each other lane, and drive every
+/// lane's movements through it. Traffic drives on the right, so southbound lanes take the western
+/// columns and westbound lanes the northern rows. With one lane per approach the cells are the
+/// four corners of the box, and a car from the north enters at the north-west one.
+fn box_paths(approaches: &HashMap<Light, Approach>) -> HashMap<LaneMovement, Vec<Zone>> {
+    let lanes = |light: &Light| approaches[light].lanes.len();
+    let columns = lanes(&Light::N) + lanes(&Light::S);
+    let rows = lanes(&Light::E) + lanes(&Light::W);
+    let mut paths = HashMap::new();
+    for (light, approach) in approaches.iter() {
+        let (width, depth) = match light {
+            Light::N | Light::S => (columns, rows),
+            Light::E | Light::W => (rows, columns),
+        };
+        // Seen from the car: `across` counts from its kerb and `ahead` from its stop line.
+        let zone = |(across, ahead): (usize, usize)| -> Zone {
+            let (x, y) = match light {
+                Light::N => (across, ahead),
+                Light::S => (columns - 1 - across, rows - 1 - ahead),
+                Light::E => (columns - 1 - ahead, across),
+                Light::W => (ahead, rows - 1 - across),
+            };
+            (y * columns + x) as Zone
+        };
+        // Turning cars keep to the lane with the same number on the road they turn into, if it
+        // has that many.
+        let lanes_right = lanes(&light.exit_side(Movement::Right).opposite());
+        let lanes_left = lanes(&light.exit_side(Movement::Left).opposite());
+        for (lane, allowed) in approach.lanes.iter().enumerate() {
+            for movement in allowed.movements.iter() {
+                let cells: Vec<(usize, usize)> = match movement {
+                    Movement::Through => (0..depth).map(|ahead| (lane, ahead)).collect(),
+                    Movement::Right => {
+                        let exit = lane.min(lanes_right - 1);
+                        (0..=exit)
+                            .map(|ahead| (lane, ahead))
+                            .chain((0..lane).rev().map(|across| (across, exit)))
+                            .collect()
+                    }
+                    Movement::Left => {
+                        let exit = depth - 1 - lane.min(lanes_left - 1);
+                        (0..=exit)
+                            .map(|ahead| (lane, ahead))
+                            .chain((lane + 1..width).map(|across| (across, exit)))
+                            .collect()
+                    }
+                };
+                paths.insert(
+                    (light.clone(), lane, *movement),
+                    cells.into_iter().map(zone).collect(),
+                );
+            }
+        }
+    }
+    paths
+}
+
+impl Geometry {
+    pub fn approach(&self, light: &Light) -> &Approach {
+        &self.approaches[light]
+    }
+    pub fn stop_line(&self, light: &Light) -> CarPos {
+        self.approach(light).stop_line()
+    }
+    pub fn lanes(&self, light: &Light) -> &[Lane] {
+        self.approach(light).lanes()
+    }
+    pub fn path(&self, light: &Light, lane: LaneId, movement: Movement) -> &[Zone] {
+        self.paths
+            .get(&(light.clone(), lane, movement))
+            .map(|zones| zones.as_slice())
+            .unwrap_or(&[])
+    }
+
+    /// The last position inside the box for a car making `movement`. Past it the car is on its
+    /// exit road.
+    pub(crate) fn last_in_box(&self, light: &Light, lane: LaneId, movement: Movement) -> CarPos {
+        self.stop_line(light) + self.path(light, lane, movement).len() as CarPos
+    }
+
+    /// The zone a car making `movement` is in at `position`, if it is inside the box.
+    pub fn zone(
+        &self,
+        light: &Light,
+        lane: LaneId,
+        movement: Movement,
+        position: CarPos,
+    ) -> Option<Zone> {
+        let stop_line = self.stop_line(light);
+        if position <= stop_line {
+            return None;
+        }
+        self.path(light, lane, movement)
+            .get((position - stop_line - 1) as usize)
+            .copied()
+    }
+}
+
+pub struct GeometryBuilder {
+    approaches: HashMap<Light, Approach>,
+    /// Paths set by hand, in place of the ones laid out from the lanes.
+    paths: HashMap<LaneMovement, Vec<Zone>>,
+}
+
+impl Default for GeometryBuilder {
+    fn default() -> Self {
+        GeometryBuilder::new()
+    }
+}
+
+impl GeometryBuilder {
+    /// Starts from the default layout.
+    pub fn new() -> Self {
+        let stop_line: CarPos = cfg().get("light_coord").unwrap();
+        let length: CarPos = cfg().get("road_length").unwrap();
+        GeometryBuilder {
+            approaches: Light::all()
+                .into_iter()
+                .map(|light| (light, Approach::new(stop_line, length)))
+                .collect(),
+            paths: HashMap::new(),
+        }
+    }
+
+    pub fn with_approach(&mut self, light: Light, approach: Approach) -> &mut Self {
+        self.approaches.insert(light, approach);
+        self
+    }
+
+    /// An empty path takes the movement straight from the stop line to its exit road, through no
+    /// zone at all.
+    pub fn with_path(
+        &mut self,
+        light: Light,
+        lane: LaneId,
+        movement: Movement,
+        zones: Vec<Zone>,
+    ) -> &mut Self {
+        self.paths.insert((light, lane, movement), zones);
+        self
+    }
+
+    pub fn build(&self) -> Geometry {
+        let mut paths = box_paths(&self.approaches);
+        paths.extend(self.paths.clone());
+        for ((light, lane, movement), zones) in paths.iter() {
+            let approach = &self.approaches[light];
+            assert!(
+                approach.stop_line() + (zones.len() as CarPos) < approach.length(),
+                "{:?} {:?} from lane {} leaves the road before it leaves the box",
+                light,
+                movement,
+                lane
+            );
+        }
+        Geometry {
+            approaches: self.approaches.clone(),
+            paths,
+        }
+    }
+}
+
+type MovementPair = (LaneMovement, LaneMovement);
+
+/// Which lane movements can collide, and where, worked out from the zones their paths share.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ConflictMatrix {
+    #[serde(with = "crate::data::pairs")]
+    conflicts: HashMap<MovementPair, Vec<(CarPos, CarPos)>>,
+}
+
+impl Default for ConflictMatrix {
+    fn default() -> Self {
+        ConflictMatrix::new(&Geometry::default())
+    }
+}
+
+impl ConflictMatrix {
+    /// Movements from the same lane queue behind each other instead of conflicting.
+    pub fn new(geometry: &Geometry) -> Self {
+        let mut conflicts = HashMap::new();
+        for (a, path_a) in geometry.paths.iter() {
+            for (b, path_b) in geometry.paths.iter() {
+                if (&a.0, a.1) == (&b.0, b.1) {
+                    continue;
+                }
+                let mut offsets = Vec::new();
+                for (i, zone) in path_a.iter().enumerate() {
+                    for (j, _) in path_b.iter().enumerate().filter(|(_, z)| *z == zone) {
+                        offsets.push((i as CarPos + 1, j as CarPos + 1));
+                    }
+                }
+                if !offsets.is_empty() {
+                    conflicts.insert((a.clone(), b.clone()), offsets);
+                }
+            }
+        }
+        ConflictMatrix { conflicts }
+    }
+
+    /// The cells past the stop line, counting from 1, at which cars making `a` and `b` are in the
+    /// same zone.
+    pub fn conflicts(&self, a: &LaneMovement, b: &LaneMovement) -> &[(CarPos, CarPos)] {
+        self.conflicts
+            .get(&(a.clone(), b.clone()))
+            .map(|offsets| offsets.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn conflict(&self, a: &LaneMovement, b: &LaneMovement) -> bool {
+        !self.conflicts(a, b).is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn perpendicular_through_movements_meet_in_one_corner() {
+        let matrix = ConflictMatrix::default();
+        let through = |light| (light, 0, Movement::Through);
+        assert_eq!(
+            matrix.conflicts(&through(Light::N), &through(Light::E)),
+            &[(1, 2)]
+        );
+        assert_eq!(
+            matrix.conflicts(&through(Light::N), &through(Light::W)),
+            &[(2, 1)]
+        );
+        assert!(!matrix.conflict(&through(Light::N), &through(Light::S)));
+    }
+
+    #[test]
+    fn permissive_left_conflicts_with_opposing_through() {
+        let matrix = ConflictMatrix::default();
+        let left = (Light::N, 0, Movement::Left);
+        let through = (Light::S, 0, Movement::Through);
+        assert_eq!(matrix.conflicts(&left, &through), &[(3, 1)]);
+        assert_eq!(matrix.conflicts(&through, &left), &[(1, 3)]);
+    }
+
+    #[test]
+    fn opposing_left_turns_only_conflict_out_of_step() {
+        let matrix = ConflictMatrix::default();
+        let mut offsets = matrix
+            .conflicts(
+                &(Light::N, 0, Movement::Left),
+                &(Light::S, 0, Movement::Left),
+            )
+            .to_vec();
+        offsets.sort_unstable();
+        assert_eq!(offsets, vec![(1, 3), (3, 1)]);
+    }
+
+    #[test]
+    fn right_turn_only_conflicts_where_it_merges() {
+        let matrix = ConflictMatrix::default();
+        let right = (Light::N, 0, Movement::Right);
+        assert!(!matrix.conflict(&right, &(Light::S, 0, Movement::Through)));
+        assert!(!matrix.conflict(&right, &(Light::W, 0, Movement::Through)));
+        assert!(matrix.conflict(&right, &(Light::E, 0, Movement::Through)));
+        assert!(matrix.conflict(&right, &(Light::S, 0, Movement::Left)));
+    }
+
+    #[test]
+    fn same_lane_never_conflicts() {
+        let matrix = ConflictMatrix::default();
+        for a in Movement::all() {
+            for b in Movement::all() {
+                assert!(!matrix.conflict(&(Light::E, 0, a), &(Light::E, 0, b)));
+            }
+        }
+    }
+
+    #[test]
+    fn zones_follow_each_approachs_stop_line() {
+        let geometry = GeometryBuilder::new()
+            .with_approach(Light::E, Approach::new(7, 12))
+            .with_path(Light::E, 0, Movement::Through, vec![9])
+            .build();
+        assert_eq!(geometry.zone(&Light::E, 0, Movement::Through, 7), None);
+        assert_eq!(geometry.zone(&Light::E, 0, Movement::Through, 8), Some(9));
+        assert_eq!(geometry.zone(&Light::E, 0, Movement::Through, 9), None);
+        // The north-west corner.
+        assert_eq!(geometry.zone(&Light::N, 0, Movement::Through, 5), Some(0));
+    }
+
+    #[test]
+    #[should_panic]
+    fn path_has_to_fit_on_the_road() {
+        GeometryBuilder::new()
+            .with_approach(Light::N, Approach::new(2, 5))
+            .build();
+    }
+
+    /// Two lanes each way, on roads long enough for the bigger box.
+    fn two_lanes() -> Geometry {
+        let mut builder = GeometryBuilder::new();
+        for light in Light::all() {
+            builder.with_approach(light, Approach::new(4, 16).with_num_lanes(2));
+        }
+        builder.build()
+    }
+
+    #[test]
+    fn side_by_side_lanes_do_not_conflict() {
+        let matrix = ConflictMatrix::new(&two_lanes());
+        assert!(!matrix.conflict(
+            &(Light::N, 0, Movement::Through),
+            &(Light::N, 1, Movement::Through)
+        ));
+        assert!(!matrix.conflict(
+            &(Light::N, 0, Movement::Right),
+            &(Light::N, 1, Movement::Left)
+        ));
+        assert!(matrix.conflict(
+            &(Light::N, 1, Movement::Left),
+            &(Light::S, 0, Movement::Through)
+        ));
+    }
+
+    #[test]
+    fn lanes_cross_every_lane_of_the_perpendicular_road() {
+        let geometry = two_lanes();
+        assert_eq!(geometry.path(&Light::E, 1, Movement::Through).len(), 4);
+        assert_eq!(geometry.path(&Light::E, 0, Movement::Right).len(), 1);
+        assert_eq!(geometry.path(&Light::E, 1, Movement::Left).len(), 5);
+        assert!(geometry.path(&Light::E, 0, Movement::Left).is_empty());
+        let matrix = ConflictMatrix::new(&geometry);
+        for lane in 0..2 {
+            for other in 0..2 {
+                assert!(matrix.conflict(
+                    &(Light::E, lane, Movement::Through),
+                    &(Light::N, other, Movement::Through)
+                ));
+            }
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Car-following models for the continuous world: how fast a car drives given the gap to the car
+//! in front of it. Distances are in cells and times in drive steps, so a car at its desired speed
+//! of 1 keeps pace with the cell-based model.
+
+/// A rule for updating a follower's speed from the gap to its leader.
+pub trait CarFollowing: Clone {
+    /// Speed after `dt` for a car at `speed`, `gap` behind the back of a leader doing
+    /// `leader_speed`. The gap is infinite on an empty road.
+    fn next_speed(&self, speed: f64, gap: f64, leader_speed: f64, dt: f64) -> f64;
+    fn desired_speed(&self) -> f64;
+    /// The braking a driver is happy to do, for deciding whether to stop on yellow.
+    fn comfortable_deceleration(&self) -> f64;
+    /// The bumper-to-bumper gap drivers leave when stopped.
+    fn standstill_gap(&self) -> f64;
+}
+
+/// The Intelligent Driver Model (Treiber, Hennecke and Helbing, 2000).
+#[derive(Clone, Debug, PartialEq)]
+pub struct Idm {
+    pub desired_speed: f64,
+    pub time_headway: f64,
+    pub max_acceleration: f64,
+    pub comfortable_deceleration: f64,
+    pub standstill_gap: f64,
+    pub exponent: f64,
+}
+
+impl Default for Idm {
+    fn default() -> Self {
+        Idm {
+            desired_speed: 1.0,
+            time_headway: 1.0,
+            max_acceleration: 0.5,
+            comfortable_deceleration: 1.0,
+            standstill_gap: 0.2,
+            exponent: 4.0,
+        }
+    }
+}
+
+impl Idm {
+    pub fn acceleration(&self, speed: f64, gap: f64, leader_speed: f64) -> f64 {
+        let free = 1.0 - (speed / self.desired_speed).powf(self.exponent);
+        if gap.is_infinite() {
+            return self.max_acceleration * free;
+        }
+        let desired_gap = self.standstill_gap
+            + (speed * self.time_headway
+                + speed * (speed - leader_speed)
+                    / (2.0 * (self.max_acceleration * self.comfortable_deceleration).sqrt()))
+            .max(0.0);
+        self.max_acceleration * (free - (desired_gap / gap.max(1e-6)).powi(2))
+    }
+}
+
+impl CarFollowing for Idm {
+    fn next_speed(&self, speed: f64, gap: f64, leader_speed: f64, dt: f64) -> f64 {
+        (speed + self.acceleration(speed, gap, leader_speed) * dt).max(0.0)
+    }
+    fn desired_speed(&self) -> f64 {
+        self.desired_speed
+    }
+    fn comfortable_deceleration(&self) -> f64 {
+        self.comfortable_deceleration
+    }
+    fn standstill_gap(&self) -> f64 {
+        self.standstill_gap
+    }
+}
+
+/// Gipps' model (1981): the lesser of a free-flow speed and the fastest speed from which the car
+/// could still stop behind its leader if the leader braked hard. The reaction time is one update.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Gipps {
+    pub desired_speed: f64,
+    pub max_acceleration: f64,
+    pub max_deceleration: f64,
+    /// What the driver guesses the leader's hardest braking to be.
+    pub leader_deceleration: f64,
+    pub standstill_gap: f64,
+}
+
+impl Default for Gipps {
+    fn default() -> Self {
+        Gipps {
+            desired_speed: 1.0,
+            max_acceleration: 0.5,
+            max_deceleration: 1.0,
+            leader_deceleration: 1.0,
+            standstill_gap: 0.2,
+        }
+    }
+}
+
+impl CarFollowing for Gipps {
+    fn next_speed(&self, speed: f64, gap: f64, leader_speed: f64, dt: f64) -> f64 {
+        let ratio = speed / self.desired_speed;
+        let free = speed
+            + 2.5 * self.max_acceleration * dt * (1.0 - ratio) * (0.025 + ratio).max(0.0).sqrt();
+        if gap.is_infinite() {
+            return free.max(0.0);
+        }
+        let b = self.max_deceleration;
+        let room = 2.0 * (gap - self.standstill_gap) - speed * dt
+            + leader_speed.powi(2) / self.leader_deceleration;
+        let safe = -b * dt + (b * b * dt * dt + b * room).max(0.0).sqrt();
+        free.min(safe).max(0.0)
+    }
+    fn desired_speed(&self) -> f64 {
+        self.desired_speed
+    }
+    fn comfortable_deceleration(&self) -> f64 {
+        self.max_deceleration
+    }
+    fn standstill_gap(&self) -> f64 {
+        self.standstill_gap
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Drive from standstill towards a stopped leader `distance` ahead, returning the final gap
+    /// and the fastest speed reached.
+    fn approach_stopped_leader<F: CarFollowing>(model: &F, distance: f64) -> (f64, f64) {
+        let dt = 0.25;
+        let (mut position, mut speed, mut fastest) = (0.0, 0.0, 0.0_f64);
+        for _ in 0..400 {
+            let next = model.next_speed(speed, distance - position, 0.0, dt);
+            position += (speed + next) / 2.0 * dt;
+            speed = next;
+            fastest = fastest.max(speed);
+        }
+        (distance - position, fastest)
+    }
+
+    #[test]
+    fn idm_stops_behind_stopped_leader() {
+        let model = Idm::default();
+        let (gap, fastest) = approach_stopped_leader(&model, 20.0);
+        assert!(gap > 0.0 && gap < 1.0, "gap {}", gap);
+        assert!(fastest <= model.desired_speed + 1e-9);
+    }
+
+    #[test]
+    fn gipps_stops_behind_stopped_leader() {
+        let model = Gipps::default();
+        let (gap, fastest) = approach_stopped_leader(&model, 20.0);
+        assert!(gap > 0.0 && gap < 1.0, "gap {}", gap);
+        assert!(fastest <= model.desired_speed + 1e-9);
+    }
+
+    #[test]
+    fn free_road_approaches_desired_speed() {
+        let idm = Idm::default();
+        let gipps = Gipps::default();
+        let (mut v_idm, mut v_gipps) = (0.0, 0.0);
+        for _ in 0..100 {
+            v_idm = idm.next_speed(v_idm, f64::INFINITY, 0.0, 0.25);
+            v_gipps = gipps.next_speed(v_gipps, f64::INFINITY, 0.0, 0.25);
+        }
+        assert!((v_idm - 1.0).abs() < 0.05);
+        assert!((v_gipps - 1.0).abs() < 0.05);
+    }
+}
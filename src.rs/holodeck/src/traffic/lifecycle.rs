@@ -0,0 +1,109 @@
+//! What happened to each car, from the step it spawned to the step it left the road or crashed.
+use crate::traffic::car::{CarId, VehicleClass};
+use crate::traffic::geometry::LaneId;
+use crate::traffic::light::Light;
+use crate::traffic::movement::Movement;
+use serde::{Deserialize, Serialize};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Outcome {
+    /// Drove off the end of the road, counting towards throughput.
+    Exited,
+    Crashed,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Lifecycle {
+    id: CarId,
+    light: Light,
+    lane: LaneId,
+    movement: Movement,
+    class: VehicleClass,
+    spawned_at: u32,
+    reached_stop_line_at: Option<u32>,
+    crossed_stop_line_at: Option<u32>,
+    ended_at: Option<u32>,
+    outcome: Option<Outcome>,
+    delay: u32,
+}
+
+impl Lifecycle {
+    pub(crate) fn new(
+        id: CarId,
+        light: Light,
+        lane: LaneId,
+        movement: Movement,
+        class: VehicleClass,
+        spawned_at: u32,
+    ) -> Self {
+        Lifecycle {
+            id,
+            light,
+            lane,
+            movement,
+            class,
+            spawned_at,
+            reached_stop_line_at: None,
+            crossed_stop_line_at: None,
+            ended_at: None,
+            outcome: None,
+            delay: 0,
+        }
+    }
+
+    pub fn id(&self) -> CarId {
+        self.id
+    }
+    /// The approach the car entered on.
+    pub fn light(&self) -> &Light {
+        &self.light
+    }
+    pub fn lane(&self) -> LaneId {
+        self.lane
+    }
+    pub fn movement(&self) -> Movement {
+        self.movement
+    }
+    pub fn class(&self) -> VehicleClass {
+        self.class
+    }
+    pub fn spawned_at(&self) -> u32 {
+        self.spawned_at
+    }
+    pub fn reached_stop_line_at(&self) -> Option<u32> {
+        self.reached_stop_line_at
+    }
+    pub fn crossed_stop_line_at(&self) -> Option<u32> {
+        self.crossed_stop_line_at
+    }
+    /// Steps spent waiting at the stop line, once the car has crossed it.
+    pub fn time_at_stop_line(&self) -> Option<u32> {
+        Some(self.crossed_stop_line_at? - self.reached_stop_line_at?)
+    }
+    pub fn ended_at(&self) -> Option<u32> {
+        self.ended_at
+    }
+    /// `None` while the car is still on the road.
+    pub fn outcome(&self) -> Option<Outcome> {
+        self.outcome
+    }
+    /// Steps the car spent standing still, i.e. how much longer it took than driving straight
+    /// through.
+    pub fn delay(&self) -> u32 {
+        self.delay
+    }
+
+    pub(crate) fn reach_stop_line(&mut self, time: u32) {
+        self.reached_stop_line_at = Some(time);
+    }
+    pub(crate) fn cross_stop_line(&mut self, time: u32) {
+        self.crossed_stop_line_at = Some(time);
+    }
+    pub(crate) fn wait(&mut self) {
+        self.delay += 1;
+    }
+    pub(crate) fn end(&mut self, time: u32, outcome: Outcome) {
+        self.ended_at = Some(time);
+        self.outcome = Some(outcome);
+    }
+}
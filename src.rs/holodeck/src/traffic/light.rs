@@ -1,8 +1,11 @@
+use crate::gatekeeper::model::ActionDistance;
+use crate::traffic::movement::Movement;
 use rand::distributions::{Distribution, Standard};
 use rand::Rng;
+use serde::{Deserialize, Serialize};
 use std::collections::HashSet;
 
-#[derive(Eq, Hash, PartialEq, Clone, Debug)]
+#[derive(Eq, Hash, PartialEq, PartialOrd, Ord, Clone, Debug, Serialize, Deserialize)]
 pub enum Light {
     N,
     S,
@@ -11,22 +14,99 @@ pub enum Light {
 }
 
 impl Light {
-    /// Returns the lights perpendicular to the current light.
-    /// Importantly, the order of the returned lights are (nearer, farther).
-    pub(crate) fn perpendiculars(&self) -> (Light, Light) {
+    pub(crate) fn all() -> [Light; 4] {
+        [Light::N, Light::S, Light::E, Light::W]
+    }
+    pub(crate) fn random(rng: &mut crate::data::prng::Rng) -> Self {
+        rng.gen()
+    }
+    pub fn opposite(&self) -> Light {
+        match self {
+            Light::N => Light::S,
+            Light::S => Light::N,
+            Light::E => Light::W,
+            Light::W => Light::E,
+        }
+    }
+    /// The side of the intersection a car from this approach leaves by when it makes `movement`.
+    /// Traffic drives on the right, so a right turn from the north heads west.
+    pub fn exit_side(&self, movement: Movement) -> Light {
+        match (self, movement) {
+            (_, Movement::Through) => self.opposite(),
+            (Light::N, Movement::Right) | (Light::S, Movement::Left) => Light::W,
+            (Light::S, Movement::Right) | (Light::N, Movement::Left) => Light::E,
+            (Light::E, Movement::Right) | (Light::W, Movement::Left) => Light::N,
+            (Light::W, Movement::Right) | (Light::E, Movement::Left) => Light::S,
+        }
+    }
+}
+
+/// The movements, by approach, that currently have a green.
+pub(crate) type CurrentlyGreen = HashSet<(Light, Movement)>;
+
+/// What light controllers request: a set of movements from a pair of opposing approaches that
+/// can be green together, or nothing green at all.
+#[derive(Eq, Hash, PartialEq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum Phase {
+    /// Every light red, holding all traffic at the stop lines. The safe thing to fall back to.
+    #[default]
+    AllRed,
+    /// Through and right turns, with left turns permitted to go when they find a gap in the
+    /// opposing traffic.
+    NorthSouth,
+    EastWest,
+    /// Protected left turns only.
+    NorthSouthLeft,
+    EastWestLeft,
+}
+
+impl Phase {
+    /// The phases that give something a green, which is everything but `AllRed`.
+    pub fn all() -> [Phase; 4] {
+        [
+            Phase::NorthSouth,
+            Phase::EastWest,
+            Phase::NorthSouthLeft,
+            Phase::EastWestLeft,
+        ]
+    }
+    /// The first phase that gives `movement` from `light` a green.
+    pub fn serving(light: &Light, movement: Movement) -> Phase {
+        Phase::all()
+            .into_iter()
+            .find(|phase| phase.movements().contains(&(light.clone(), movement)))
+            .expect("every movement is in some phase")
+    }
+    /// The approaches that get some green in this phase.
+    pub fn lights(&self) -> HashSet<Light> {
         match self {
-            Light::N => (Light::E, Light::W),
-            Light::S => (Light::W, Light::E),
-            Light::E => (Light::S, Light::N),
-            Light::W => (Light::N, Light::S),
+            Phase::AllRed => HashSet::new(),
+            Phase::NorthSouth | Phase::NorthSouthLeft => [Light::N, Light::S].into_iter().collect(),
+            Phase::EastWest | Phase::EastWestLeft => [Light::E, Light::W].into_iter().collect(),
         }
     }
-    pub(crate) fn random() -> Self {
-        rand::random()
+    pub fn movements(&self) -> HashSet<(Light, Movement)> {
+        let turns: &[Movement] = match self {
+            Phase::AllRed => &[],
+            Phase::NorthSouth | Phase::EastWest => &Movement::all(),
+            Phase::NorthSouthLeft | Phase::EastWestLeft => &[Movement::Left],
+        };
+        self.lights()
+            .into_iter()
+            .flat_map(|light| turns.iter().map(move |turn| (light.clone(), *turn)))
+            .collect()
     }
 }
 
-pub(crate) type CurrentlyGreen = HashSet<Light>;
+/// The number of movements green in one phase but not the other, so a phase that keeps most of
+/// the same traffic moving is nearer than one that stops it.
+impl ActionDistance for Phase {
+    fn distance(&self, other: &Self) -> u32 {
+        self.movements()
+            .symmetric_difference(&other.movements())
+            .count() as u32
+    }
+}
 
 impl Distribution<Light> for Standard {
     fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Light {
@@ -39,3 +119,26 @@ impl Distribution<Light> for Standard {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn phases_sharing_green_movements_are_nearer() {
+        let distance = |a: Phase, b: Phase| a.distance(&b);
+        assert_eq!(distance(Phase::NorthSouth, Phase::NorthSouth), 0);
+        assert!(
+            distance(Phase::NorthSouth, Phase::NorthSouthLeft)
+                < distance(Phase::NorthSouth, Phase::EastWestLeft)
+        );
+        assert!(
+            distance(Phase::NorthSouthLeft, Phase::AllRed)
+                < distance(Phase::NorthSouth, Phase::AllRed)
+        );
+        assert!(
+            distance(Phase::NorthSouth, Phase::AllRed)
+                < distance(Phase::NorthSouth, Phase::EastWest)
+        );
+    }
+}
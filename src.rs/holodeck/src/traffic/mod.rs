@@ -1,6 +1,21 @@
 pub mod car;
+pub mod checkpoint;
+pub mod continuous;
+pub mod controllers;
+pub mod demand;
+pub mod geometry;
 pub mod intersection;
+pub mod kinematics;
+pub mod lifecycle;
 pub mod light;
+pub mod movement;
+pub mod network;
+pub mod observation;
+pub mod pedestrian;
+pub mod recording;
+pub mod signal;
 pub mod simulation;
+pub mod specs;
 #[macro_use]
 pub mod trajectory;
+pub mod world;
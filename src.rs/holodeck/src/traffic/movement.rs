@@ -0,0 +1,29 @@
+//! Where a car goes once it crosses the stop line.
+use rand::distributions::{Distribution, Standard};
+use rand::Rng;
+use serde::{Deserialize, Serialize};
+
+#[derive(Eq, Hash, PartialEq, Clone, Copy, Debug, Default, Serialize, Deserialize)]
+pub enum Movement {
+    Left,
+    #[default]
+    Through,
+    Right,
+}
+
+impl Movement {
+    pub fn all() -> [Movement; 3] {
+        [Movement::Left, Movement::Through, Movement::Right]
+    }
+}
+
+impl Distribution<Movement> for Standard {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> Movement {
+        match rng.gen_range(0..=2) {
+            0 => Movement::Left,
+            1 => Movement::Through,
+            2 => Movement::Right,
+            _ => unreachable!(),
+        }
+    }
+}
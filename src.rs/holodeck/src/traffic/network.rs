@@ -0,0 +1,576 @@
+//! Intersections joined into a road network. A car that leaves one intersection onto a link
+//! drives along it and joins the facing approach of the next intersection on its route. Each
+//! intersection keeps its own lights and controller.
+use rand::Rng as _;
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt::{Display, Formatter, Result};
+
+use crate::data::prng::Rng;
+use crate::traffic::car::CarId;
+use crate::traffic::intersection::{Intersection, IntersectionBuilder};
+use crate::traffic::light::Light;
+use crate::traffic::movement::Movement;
+use crate::traffic::simulation::LightController;
+
+/// An intersection's index in the network.
+pub type NodeId = usize;
+pub type TripId = u32;
+
+/// The movement a car makes at each intersection it comes to, in order.
+pub type Route = VecDeque<Movement>;
+
+/// A road leaving an intersection by one side and ending at the facing approach of another.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Link {
+    to: NodeId,
+    /// Steps it takes to drive from one end to the other.
+    length: u32,
+}
+
+impl Link {
+    pub fn to(&self) -> NodeId {
+        self.to
+    }
+    pub fn length(&self) -> u32 {
+        self.length
+    }
+}
+
+/// One car's journey through the network.
+#[derive(Clone, Debug)]
+struct Trip {
+    id: TripId,
+    route: Route,
+    started_at: u32,
+}
+
+#[derive(Clone)]
+struct Node<C: LightController> {
+    intersection: Intersection,
+    controller: C,
+}
+
+/// What happened across the whole network over one decision period.
+#[derive(Debug, Clone, PartialEq, Eq, Copy)]
+pub struct NetworkEntry {
+    num_crashes_local: u32,
+    num_trips_completed: u32,
+    num_vehicles: u32,
+}
+
+impl Display for NetworkEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> Result {
+        write!(
+            f,
+            "NetworkEntry(num_crashes_local={}, num_trips_completed={}, num_vehicles={})",
+            self.num_crashes_local, self.num_trips_completed, self.num_vehicles
+        )
+    }
+}
+
+impl NetworkEntry {
+    pub fn num_crashes_local(&self) -> u32 {
+        self.num_crashes_local
+    }
+    pub fn num_trips_completed(&self) -> u32 {
+        self.num_trips_completed
+    }
+    /// Cars in the network at the end of the period, on intersections or links.
+    pub fn num_vehicles(&self) -> u32 {
+        self.num_vehicles
+    }
+}
+
+#[derive(Clone)]
+pub struct Network<C: LightController> {
+    nodes: Vec<Node<C>>,
+    /// Keyed by the intersection the link leaves and the side it leaves by.
+    links: BTreeMap<(NodeId, Light), Link>,
+    /// The trip of every car on an intersection, by the intersection and the car's id there.
+    trips: BTreeMap<(NodeId, CarId), Trip>,
+    /// Cars on each link in the order they joined it, with the step they reach its end.
+    in_transit: BTreeMap<(NodeId, Light), VecDeque<(u32, Trip)>>,
+    next_trip: TripId,
+    time: u32,
+    trips_completed: u32,
+    total_travel_time: u32,
+    max_cars: u32,
+    drive_steps_per_lightswitch: u32,
+    max_steps: u32,
+}
+
+pub struct NetworkBuilder<C: LightController> {
+    network: Network<C>,
+}
+
+impl<C: LightController> Default for NetworkBuilder<C> {
+    fn default() -> Self {
+        NetworkBuilder::new()
+    }
+}
+
+impl<C: LightController> NetworkBuilder<C> {
+    pub fn new() -> Self {
+        NetworkBuilder {
+            network: Network {
+                nodes: Vec::new(),
+                links: BTreeMap::new(),
+                trips: BTreeMap::new(),
+                in_transit: BTreeMap::new(),
+                next_trip: 0,
+                time: 0,
+                trips_completed: 0,
+                total_travel_time: 0,
+                max_cars: 0,
+                drive_steps_per_lightswitch: 0,
+                max_steps: 0,
+            },
+        }
+    }
+
+    /// `rows` by `cols` default intersections, numbered row by row from the north-west, with a
+    /// link each way between neighbours.
+    pub fn grid(rows: usize, cols: usize, link_length: u32) -> Self {
+        let mut builder = NetworkBuilder::new();
+        for _ in 0..(rows * cols) {
+            builder = builder.with_intersection(IntersectionBuilder::new().build());
+        }
+        for row in 0..rows {
+            for col in 0..cols {
+                let node = row * cols + col;
+                if col + 1 < cols {
+                    builder = builder
+                        .with_link(node, Light::E, node + 1, link_length)
+                        .with_link(node + 1, Light::W, node, link_length);
+                }
+                if row + 1 < rows {
+                    builder = builder
+                        .with_link(node, Light::S, node + cols, link_length)
+                        .with_link(node + cols, Light::N, node, link_length);
+                }
+            }
+        }
+        builder
+    }
+
+    /// `n` intersections in a row from west to east.
+    pub fn corridor(n: usize, link_length: u32) -> Self {
+        NetworkBuilder::grid(1, n, link_length)
+    }
+
+    /// Add an intersection run by a default controller. Its id is the number added before it.
+    pub fn with_intersection(mut self, intersection: Intersection) -> Self {
+        self.network.nodes.push(Node {
+            intersection,
+            controller: C::default(),
+        });
+        self
+    }
+
+    pub fn with_controller(mut self, node: NodeId, controller: C) -> Self {
+        self.network.nodes[node].controller = controller;
+        self
+    }
+
+    /// Join the `side` exit of `from` to the approach of `to` facing it.
+    pub fn with_link(mut self, from: NodeId, side: Light, to: NodeId, length: u32) -> Self {
+        assert!(
+            from < self.network.nodes.len() && to < self.network.nodes.len(),
+            "link between unknown intersections"
+        );
+        self.network.links.insert((from, side), Link { to, length });
+        self
+    }
+
+    /// The most cars in the whole network at once.
+    pub fn with_max_cars(mut self, max_cars: u32) -> Self {
+        self.network.max_cars = max_cars;
+        self
+    }
+
+    pub fn with_drive_steps_per_lightswitch(mut self, drive_steps_per_lightswitch: u32) -> Self {
+        self.network.drive_steps_per_lightswitch = drive_steps_per_lightswitch;
+        self
+    }
+
+    pub fn with_max_steps(mut self, max_steps: u32) -> Self {
+        self.network.max_steps = max_steps;
+        self
+    }
+
+    pub fn build(self) -> Network<C> {
+        self.network
+    }
+}
+
+impl<C: LightController> Network<C> {
+    pub fn num_intersections(&self) -> usize {
+        self.nodes.len()
+    }
+    pub fn intersection(&self, node: NodeId) -> &Intersection {
+        &self.nodes[node].intersection
+    }
+    pub fn controller(&self, node: NodeId) -> &C {
+        &self.nodes[node].controller
+    }
+    pub fn link(&self, from: NodeId, side: &Light) -> Option<&Link> {
+        self.links.get(&(from, side.clone()))
+    }
+    pub fn time(&self) -> u32 {
+        self.time
+    }
+
+    /// Crashes on every intersection so far.
+    pub fn num_crashes(&self) -> u32 {
+        self.nodes
+            .iter()
+            .map(|node| node.intersection.num_crashes())
+            .sum()
+    }
+    /// Cars that have left the network at the end of their route.
+    pub fn trips_completed(&self) -> u32 {
+        self.trips_completed
+    }
+    /// Steps from entering to leaving the network, over every completed trip.
+    pub fn total_travel_time(&self) -> u32 {
+        self.total_travel_time
+    }
+    pub fn mean_travel_time(&self) -> Option<f64> {
+        (self.trips_completed > 0)
+            .then(|| self.total_travel_time as f64 / self.trips_completed as f64)
+    }
+    /// The trip the car with id `car` on `node`'s intersection is making. None for a car that
+    /// wasn't spawned on a trip through the network.
+    pub fn trip(&self, node: NodeId, car: CarId) -> Option<TripId> {
+        self.trips.get(&(node, car)).map(|trip| trip.id)
+    }
+    /// Cars on the intersections and on the links between them.
+    pub fn num_vehicles(&self) -> u32 {
+        let on_links: usize = self.in_transit.values().map(|cars| cars.len()).sum();
+        self.trips.len() as u32 + on_links as u32
+    }
+
+    /// The approaches no link leads to, where cars enter the network.
+    pub fn entrances(&self) -> Vec<(NodeId, Light)> {
+        (0..self.nodes.len())
+            .flat_map(|node| Light::all().into_iter().map(move |light| (node, light)))
+            .filter(|(node, light)| {
+                !self
+                    .links
+                    .iter()
+                    .any(|((_, side), link)| link.to == *node && side.opposite() == *light)
+            })
+            .collect()
+    }
+
+    /// Draw a movement at each intersection in turn from `node`'s `light` approach, until the car
+    /// would leave the network or has crossed as many intersections as there are.
+    pub(crate) fn random_route(&self, mut node: NodeId, mut light: Light, rng: &mut Rng) -> Route {
+        let mut route = Route::new();
+        loop {
+            let movement: Movement = rng.gen();
+            route.push_back(movement);
+            let side = light.exit_side(movement);
+            match self.link(node, &side) {
+                Some(link) if route.len() < self.nodes.len() => {
+                    node = link.to;
+                    light = side.opposite();
+                }
+                _ => return route,
+            }
+        }
+    }
+
+    /// Start a trip along `route` from `node`'s `light` approach, if a lane for its first
+    /// movement has a free entrance.
+    pub fn spawn_car(&mut self, node: NodeId, light: Light, mut route: Route) -> Option<TripId> {
+        let intersection = &mut self.nodes[node].intersection;
+        let movement = route.pop_front().unwrap_or_default();
+        let lane = intersection.assign_lane(&light, movement)?;
+        let car = intersection.spawn_car_in_lane(light, lane, movement);
+        let id = self.next_trip;
+        self.next_trip += 1;
+        let trip = Trip {
+            id,
+            route,
+            started_at: self.time,
+        };
+        self.trips.insert((node, car), trip);
+        Some(id)
+    }
+
+    pub(crate) fn spawn_random_car(&mut self, rng: &mut Rng) {
+        if rng.gen::<bool>() && self.num_vehicles() < self.max_cars {
+            let entrances = self.entrances();
+            if entrances.is_empty() {
+                return;
+            }
+            let (node, light) = entrances[rng.gen_range(0..entrances.len())].clone();
+            let route = self.random_route(node, light.clone(), rng);
+            self.spawn_car(node, light, route);
+        }
+    }
+
+    /// Move cars at the end of each link onto the next approach, in the order they joined the
+    /// link. A car whose lanes have backed up to the entrance holds up the link behind it.
+    fn release_links(&mut self) {
+        for ((from, side), cars) in self.in_transit.iter_mut() {
+            let link = &self.links[&(*from, side.clone())];
+            let light = side.opposite();
+            while let Some((arrival, trip)) = cars.front() {
+                let intersection = &mut self.nodes[link.to].intersection;
+                let movement = trip.route.front().copied().unwrap_or_default();
+                let lane = match intersection.assign_lane(&light, movement) {
+                    Some(lane) if *arrival <= self.time => lane,
+                    _ => break,
+                };
+                let (_, mut trip) = cars.pop_front().unwrap();
+                trip.route.pop_front();
+                let car = intersection.spawn_car_in_lane(light.clone(), lane, movement);
+                self.trips.insert((link.to, car), trip);
+            }
+        }
+    }
+
+    /// Put the cars that left each intersection onto their next link, or out of the network,
+    /// and forget the trips of cars that crashed.
+    fn collect_exits(&mut self) {
+        for node in 0..self.nodes.len() {
+            let exited = self.nodes[node].intersection.exited().to_vec();
+            for car in exited {
+                let trip = match self.trips.remove(&(node, car.id)) {
+                    Some(trip) => trip,
+                    // Put on the intersection directly rather than sent on a trip, so it just
+                    // leaves.
+                    None => continue,
+                };
+                let side = car.light.exit_side(car.movement);
+                match self.links.get(&(node, side.clone())) {
+                    Some(link) if !trip.route.is_empty() => {
+                        self.in_transit
+                            .entry((node, side))
+                            .or_default()
+                            .push_back((self.time + link.length, trip));
+                    }
+                    _ => {
+                        self.trips_completed += 1;
+                        self.total_travel_time += self.time - trip.started_at;
+                    }
+                }
+            }
+        }
+        let nodes = &self.nodes;
+        self.trips.retain(|(node, car), _| {
+            nodes[*node]
+                .intersection
+                .lifecycle(*car)
+                .is_some_and(|lifecycle| lifecycle.ended_at().is_none())
+        });
+    }
+
+    /// One drive step on every intersection and link.
+    pub(crate) fn advance(&mut self) {
+        self.time += 1;
+        for node in self.nodes.iter_mut() {
+            node.intersection.advance();
+        }
+        self.collect_exits();
+        self.release_links();
+    }
+
+    /// Show each controller its own intersection, then apply the phase it selects.
+    pub(crate) fn ask_controllers(&mut self, rng: &mut Rng) {
+        for node in self.nodes.iter_mut() {
+            let observation = node.intersection.observe();
+            let phase = node.controller.select_action(&observation, rng);
+            node.intersection.request_phase(phase);
+        }
+    }
+
+    pub(crate) fn drive_between_lightswitch(&mut self, rng: &mut Rng) {
+        for _ in 0..self.drive_steps_per_lightswitch {
+            self.spawn_random_car(rng);
+            self.advance();
+        }
+    }
+
+    pub fn run(&mut self, rng: &mut Rng) {
+        for _ in 0..self.max_steps {
+            self.ask_controllers(rng);
+            self.drive_between_lightswitch(rng);
+        }
+    }
+
+    /// Run for `max_steps` decision periods, recording what happened in each.
+    pub fn run_recording(&mut self, rng: &mut Rng) -> Vec<NetworkEntry> {
+        (0..self.max_steps)
+            .map(|_| {
+                let crashes_before = self.num_crashes();
+                let completed_before = self.trips_completed;
+                self.ask_controllers(rng);
+                self.drive_between_lightswitch(rng);
+                NetworkEntry {
+                    num_crashes_local: self.num_crashes() - crashes_before,
+                    num_trips_completed: self.trips_completed - completed_before,
+                    num_vehicles: self.num_vehicles(),
+                }
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::cfg;
+    use crate::traffic::controllers::FixedTime;
+    use crate::traffic::lifecycle::Lifecycle;
+    use crate::traffic::light::Phase;
+    use rand::SeedableRng;
+
+    fn green(phase: Phase) -> FixedTime {
+        FixedTime::new(vec![(phase, 1)])
+    }
+
+    #[test]
+    fn corridor_joins_neighbours_both_ways() {
+        let network: Network<FixedTime> = NetworkBuilder::corridor(3, 5).build();
+        assert_eq!(network.num_intersections(), 3);
+        assert_eq!(network.link(0, &Light::E).map(Link::to), Some(1));
+        assert_eq!(network.link(2, &Light::W).map(Link::to), Some(1));
+        assert!(network.link(2, &Light::E).is_none());
+        assert!(!network.entrances().contains(&(1, Light::W)));
+        assert!(network.entrances().contains(&(0, Light::W)));
+        assert_eq!(network.entrances().len(), 8);
+    }
+
+    #[test]
+    fn car_follows_route_along_corridor() {
+        let road_length: u32 = cfg().get("road_length").unwrap();
+        let link_length = 3;
+        let mut network = NetworkBuilder::corridor(2, link_length)
+            .with_controller(0, green(Phase::EastWest))
+            .with_controller(1, green(Phase::EastWest))
+            .build();
+        let mut rng = Rng::seed_from_u64(0);
+        network.ask_controllers(&mut rng);
+        let route: Route = [Movement::Through, Movement::Right].into_iter().collect();
+        network.spawn_car(0, Light::W, route).unwrap();
+        for _ in 0..road_length {
+            network.advance();
+        }
+        assert_eq!(network.intersection(0).total_throughput(), 1);
+        assert_eq!(network.num_vehicles(), 1);
+        for _ in 0..(link_length + road_length) {
+            network.advance();
+        }
+        let lifecycles = network.intersection(1).lifecycles();
+        assert_eq!(lifecycles.len(), 1);
+        assert_eq!(lifecycles[0].light(), &Light::W);
+        assert_eq!(lifecycles[0].movement(), Movement::Right);
+        assert_eq!(network.trips_completed(), 1);
+        assert_eq!(network.num_vehicles(), 0);
+        assert_eq!(
+            network.mean_travel_time(),
+            Some((2 * road_length + link_length) as f64)
+        );
+    }
+
+    #[test]
+    fn trip_id_follows_the_car_onto_the_next_intersection() {
+        let mut network = NetworkBuilder::corridor(2, 3)
+            .with_controller(0, green(Phase::EastWest))
+            .with_controller(1, green(Phase::EastWest))
+            .build();
+        network.ask_controllers(&mut Rng::seed_from_u64(0));
+        let route: Route = [Movement::Through, Movement::Through].into_iter().collect();
+        let id = network.spawn_car(0, Light::W, route).unwrap();
+        assert_eq!(
+            network.trip(0, network.intersection(0).cars[0].id),
+            Some(id)
+        );
+        while network.intersection(1).cars.is_empty() {
+            network.advance();
+        }
+        let car = network.intersection(1).cars[0].id;
+        assert_eq!(network.trip(1, car), Some(id));
+    }
+
+    #[test]
+    fn car_put_on_an_intersection_directly_just_leaves() {
+        let road_length: u32 = cfg().get("road_length").unwrap();
+        let mut network = NetworkBuilder::corridor(2, 3)
+            .with_controller(0, green(Phase::EastWest))
+            .build();
+        network.ask_controllers(&mut Rng::seed_from_u64(0));
+        let car = network.nodes[0].intersection.spawn_car(Light::W);
+        assert_eq!(network.trip(0, car), None);
+        for _ in 0..(2 * road_length) {
+            network.advance();
+        }
+        assert_eq!(network.intersection(0).total_throughput(), 1);
+        assert_eq!(network.trips_completed(), 0);
+        assert_eq!(network.num_vehicles(), 0);
+    }
+
+    #[test]
+    fn random_routes_end_where_the_network_does() {
+        let network: Network<FixedTime> = NetworkBuilder::grid(2, 2, 2).build();
+        let mut rng = Rng::seed_from_u64(0);
+        for _ in 0..32 {
+            let route = network.random_route(0, Light::N, &mut rng);
+            assert!(!route.is_empty() && route.len() <= 4);
+        }
+    }
+
+    #[test]
+    fn same_seed_replays_the_same_run() {
+        let run = || {
+            let mut network: Network<FixedTime> = NetworkBuilder::grid(3, 3, 1)
+                .with_max_cars(64)
+                .with_drive_steps_per_lightswitch(4)
+                .with_max_steps(32)
+                .build();
+            let entries = network.run_recording(&mut Rng::seed_from_u64(3));
+            let trips: Vec<(NodeId, CarId, TripId)> = network
+                .trips
+                .iter()
+                .map(|(&(node, car), trip)| (node, car, trip.id))
+                .collect();
+            let lifecycles: Vec<Vec<Lifecycle>> = (0..network.num_intersections())
+                .map(|node| {
+                    network
+                        .intersection(node)
+                        .lifecycles()
+                        .into_iter()
+                        .cloned()
+                        .collect()
+                })
+                .collect();
+            (entries, network.total_travel_time(), trips, lifecycles)
+        };
+        assert_eq!(run(), run());
+    }
+
+    #[test]
+    fn grid_records_network_metrics() {
+        let mut network: Network<FixedTime> = NetworkBuilder::grid(2, 2, 2)
+            .with_max_cars(16)
+            .with_drive_steps_per_lightswitch(4)
+            .with_max_steps(16)
+            .build();
+        let mut rng = Rng::seed_from_u64(0);
+        let entries = network.run_recording(&mut rng);
+        assert_eq!(entries.len(), 16);
+        let completed: u32 = entries.iter().map(|e| e.num_trips_completed()).sum();
+        let crashes: u32 = entries.iter().map(|e| e.num_crashes_local()).sum();
+        assert_eq!(completed, network.trips_completed());
+        assert_eq!(crashes, network.num_crashes());
+        assert!(completed > 0);
+        assert_eq!(
+            entries.last().unwrap().num_vehicles(),
+            network.num_vehicles()
+        );
+        assert!(network.num_vehicles() <= 16);
+    }
+}
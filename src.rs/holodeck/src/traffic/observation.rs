@@ -0,0 +1,223 @@
+//! What a controller gets to see of the intersection before it requests a phase.
+use crate::traffic::car::{Car, CarPos, VehicleClass};
+use crate::traffic::geometry::{Geometry, LaneId};
+use crate::traffic::intersection::Intersection;
+use crate::traffic::light::{CurrentlyGreen, Light, Phase};
+use crate::traffic::movement::Movement;
+use crate::traffic::signal::{Signal, SignalState};
+use std::collections::HashMap;
+
+/// How many cells upstream of the stop line still count as "near" it.
+static NEAR_STOP_LINE: CarPos = 3;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Observation {
+    queue_lengths: HashMap<(Light, Movement), u32>,
+    lane_queue_lengths: HashMap<(Light, LaneId), u32>,
+    downstream_lengths: HashMap<(Light, Movement), u32>,
+    near_stop_line: HashMap<Light, Vec<CarPos>>,
+    signal_states: HashMap<Light, SignalState>,
+    green_lights: CurrentlyGreen,
+    phase: Option<Phase>,
+    steps_since_switch: u32,
+    pedestrians_waiting: HashMap<Light, u32>,
+    preemption: Option<(Light, Movement)>,
+}
+
+impl Observation {
+    pub(crate) fn new(intersection: &Intersection) -> Self {
+        let mut observation = Observation::of_cars(
+            &intersection.cars,
+            intersection.geometry(),
+            &intersection.signal,
+        );
+        observation.pedestrians_waiting = Light::all()
+            .into_iter()
+            .map(|leg| {
+                let waiting = intersection.pedestrians_waiting(&leg);
+                (leg, waiting)
+            })
+            .collect();
+        observation
+    }
+
+    /// What a controller would see of `cars` laid out on `geometry` under `signal`.
+    pub(crate) fn of_cars(cars: &[Car], geometry: &Geometry, signal: &Signal) -> Self {
+        let mut queue_lengths: HashMap<(Light, Movement), u32> = HashMap::new();
+        let mut lane_queue_lengths: HashMap<(Light, LaneId), u32> = HashMap::new();
+        let mut downstream_lengths: HashMap<(Light, Movement), u32> = HashMap::new();
+        let mut near_stop_line: HashMap<Light, Vec<CarPos>> = Light::all()
+            .into_iter()
+            .map(|light| (light, Vec::new()))
+            .collect();
+        for car in cars.iter() {
+            let movement = (car.light.clone(), car.movement);
+            let stop_line = geometry.stop_line(&car.light);
+            if car.position <= stop_line {
+                *queue_lengths.entry(movement).or_default() += 1;
+                *lane_queue_lengths
+                    .entry((car.light.clone(), car.lane))
+                    .or_default() += 1;
+                if car.position + NEAR_STOP_LINE >= stop_line {
+                    near_stop_line
+                        .entry(car.light.clone())
+                        .or_default()
+                        .push(car.position);
+                }
+            } else {
+                *downstream_lengths.entry(movement).or_default() += 1;
+            }
+        }
+        let preemption = cars
+            .iter()
+            .filter(|car| {
+                car.class == VehicleClass::Emergency
+                    && car.position <= geometry.stop_line(&car.light)
+            })
+            .min_by_key(|car| geometry.stop_line(&car.light) - car.position)
+            .map(|car| (car.light.clone(), car.movement));
+        for positions in near_stop_line.values_mut() {
+            positions.sort_unstable_by(|a, b| b.cmp(a));
+        }
+        Observation {
+            queue_lengths,
+            lane_queue_lengths,
+            downstream_lengths,
+            near_stop_line,
+            signal_states: Light::all()
+                .into_iter()
+                .map(|light| {
+                    let state = signal.state(&light);
+                    (light, state)
+                })
+                .collect(),
+            green_lights: signal.green_lights(),
+            phase: signal.phase(),
+            steps_since_switch: signal.steps_in_stage(),
+            pedestrians_waiting: HashMap::new(),
+            preemption,
+        }
+    }
+
+    /// Cars on the approach that have not yet crossed the stop line.
+    pub fn queue_length(&self, light: &Light) -> u32 {
+        Movement::all()
+            .into_iter()
+            .map(|movement| self.queue_length_of(light, movement))
+            .sum()
+    }
+
+    /// Cars on the approach waiting to make `movement`.
+    pub fn queue_length_of(&self, light: &Light, movement: Movement) -> u32 {
+        self.queue_lengths
+            .get(&(light.clone(), movement))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Cars in one lane of the approach that have not yet crossed the stop line.
+    pub fn queue_length_in_lane(&self, light: &Light, lane: LaneId) -> u32 {
+        self.lane_queue_lengths
+            .get(&(light.clone(), lane))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Cars on the approach that have crossed the stop line but not yet left the road.
+    pub fn downstream_length(&self, light: &Light) -> u32 {
+        Movement::all()
+            .into_iter()
+            .map(|movement| self.downstream_length_of(light, movement))
+            .sum()
+    }
+
+    pub fn downstream_length_of(&self, light: &Light, movement: Movement) -> u32 {
+        self.downstream_lengths
+            .get(&(light.clone(), movement))
+            .copied()
+            .unwrap_or(0)
+    }
+
+    /// Positions of the cars a few cells upstream of the stop line, closest first.
+    pub fn near_stop_line(&self, light: &Light) -> &[CarPos] {
+        self.near_stop_line
+            .get(light)
+            .map(|positions| positions.as_slice())
+            .unwrap_or(&[])
+    }
+
+    pub fn signal_state(&self, light: &Light) -> SignalState {
+        self.signal_states[light]
+    }
+
+    /// The movements, by approach, that have a green.
+    pub fn green_lights(&self) -> &CurrentlyGreen {
+        &self.green_lights
+    }
+
+    /// The phase that is green, if any. `None` during yellow and all-red.
+    pub fn phase(&self) -> Option<Phase> {
+        self.phase
+    }
+
+    /// Pedestrians at the kerb waiting for a walk signal to cross `leg`.
+    pub fn pedestrians_waiting(&self, leg: &Light) -> u32 {
+        self.pedestrians_waiting.get(leg).copied().unwrap_or(0)
+    }
+
+    /// The movement an approaching emergency vehicle needs a green for. If there are several,
+    /// the one closest to its stop line.
+    pub fn preemption(&self) -> Option<(Light, Movement)> {
+        self.preemption.clone()
+    }
+
+    /// Steps driven since the signal last changed between green, yellow and all-red.
+    pub fn steps_since_switch(&self) -> u32 {
+        self.steps_since_switch
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::cfg;
+    use crate::traffic::intersection::IntersectionBuilder;
+
+    #[test]
+    fn observation_counts_queues_upstream_of_stop_line() {
+        let light_coord: u32 = cfg().get("light_coord").unwrap();
+        let mut intersection = IntersectionBuilder::new().add_light(Light::N).build();
+        intersection.spawn_car(Light::N);
+        for _ in 0..(light_coord + 1) {
+            intersection.advance();
+        }
+        intersection.spawn_car(Light::N);
+        intersection.spawn_car(Light::E);
+        let observation = intersection.observe();
+        assert_eq!(observation.queue_length(&Light::N), 1);
+        assert_eq!(observation.queue_length(&Light::E), 1);
+        assert_eq!(observation.queue_length(&Light::S), 0);
+        assert_eq!(observation.downstream_length(&Light::N), 1);
+        assert!(observation
+            .green_lights()
+            .contains(&(Light::N, Movement::Through)));
+        assert_eq!(observation.steps_since_switch(), light_coord + 1);
+    }
+
+    #[test]
+    fn approaching_emergency_vehicle_requests_preemption() {
+        let light_coord: u32 = cfg().get("light_coord").unwrap();
+        let mut intersection = IntersectionBuilder::new().build();
+        intersection.spawn_vehicle_in_lane(Light::W, 0, Movement::Left, VehicleClass::Emergency);
+        intersection.spawn_car(Light::N);
+        assert_eq!(
+            intersection.observe().preemption(),
+            Some((Light::W, Movement::Left))
+        );
+        intersection.request_phase(Phase::EastWest);
+        for _ in 0..(light_coord + 1) {
+            intersection.advance();
+        }
+        assert_eq!(intersection.observe().preemption(), None);
+    }
+}
@@ -0,0 +1,112 @@
+//! People crossing the legs of the intersection. Every leg has a crosswalk just past the stop line.
+//! Pedestrians wait at the kerb for a walk signal, then take a fixed number of drive steps to
+//! get across.
+use crate::traffic::light::Light;
+use serde::{Deserialize, Serialize};
+
+pub type PedestrianId = u32;
+
+static CROSSING_STEPS: u32 = 2;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub(crate) struct Pedestrian {
+    pub id: PedestrianId,
+    /// The leg whose crosswalk they are crossing.
+    pub leg: Light,
+    /// Steps spent on the crosswalk. `None` while waiting at the kerb.
+    pub progress: Option<u32>,
+}
+
+impl Pedestrian {
+    pub(crate) fn new(id: PedestrianId, leg: Light) -> Self {
+        Pedestrian {
+            id,
+            leg,
+            progress: None,
+        }
+    }
+
+    pub(crate) fn is_crossing(&self) -> bool {
+        self.progress.is_some()
+    }
+}
+
+/// Everything about the pedestrians at one intersection.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub(crate) struct Crosswalks {
+    pub pedestrians: Vec<Pedestrian>,
+    next_id: PedestrianId,
+    /// Drive steps it takes to get across.
+    pub crossing_steps: u32,
+    pub num_incidents: u32,
+    pub num_crossed: u32,
+}
+
+impl Default for Crosswalks {
+    fn default() -> Self {
+        Crosswalks {
+            pedestrians: Vec::new(),
+            next_id: 0,
+            crossing_steps: CROSSING_STEPS,
+            num_incidents: 0,
+            num_crossed: 0,
+        }
+    }
+}
+
+impl Crosswalks {
+    pub(crate) fn spawn(&mut self, leg: Light) -> PedestrianId {
+        let id = self.next_id;
+        self.next_id += 1;
+        self.pedestrians.push(Pedestrian::new(id, leg));
+        id
+    }
+
+    /// Step pedestrians on the crosswalk forward, let those waiting at a leg showing walk start
+    /// across, and take off those who have made it to the other side.
+    pub(crate) fn walk(&mut self, walk: impl Fn(&Light) -> bool) {
+        let crossing_steps = self.crossing_steps;
+        for pedestrian in self.pedestrians.iter_mut() {
+            pedestrian.progress = match pedestrian.progress {
+                Some(steps) => Some(steps + 1),
+                None if walk(&pedestrian.leg) => Some(0),
+                None => None,
+            };
+        }
+        let before = self.pedestrians.len();
+        self.pedestrians.retain(|pedestrian| {
+            pedestrian
+                .progress
+                .is_none_or(|steps| steps < crossing_steps)
+        });
+        self.num_crossed += (before - self.pedestrians.len()) as u32;
+    }
+
+    pub(crate) fn waiting(&self, leg: &Light) -> u32 {
+        self.pedestrians
+            .iter()
+            .filter(|pedestrian| &pedestrian.leg == leg && !pedestrian.is_crossing())
+            .count() as u32
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pedestrians_wait_for_walk_then_take_crossing_steps() {
+        let mut crosswalks = Crosswalks::default();
+        crosswalks.spawn(Light::N);
+        crosswalks.walk(|_| false);
+        assert_eq!(crosswalks.waiting(&Light::N), 1);
+        crosswalks.walk(|_| true);
+        assert!(crosswalks.pedestrians[0].is_crossing());
+        // Once across they carry on, walk or not.
+        for _ in 0..crosswalks.crossing_steps {
+            crosswalks.walk(|_| false);
+        }
+        assert!(crosswalks.pedestrians.is_empty());
+        assert_eq!(crosswalks.num_crossed, 1);
+    }
+}
@@ -0,0 +1,305 @@
+//! Trajectories on disk, as CSV or JSON Lines, so a run can be checked against specs, plotted or
+//! kept as a regression fixture long after it happened. Both formats start with a header saying
+//! what produced the trajectory.
+use crate::cfg::cfg;
+use crate::traffic::car::CarId;
+use crate::traffic::light::Light;
+use crate::traffic::movement::Movement;
+use crate::traffic::trajectory::{Trajectory, TrajectoryEntry};
+use config::Source as _;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::{self, BufRead, Write};
+
+/// What a trajectory was recorded from.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Header {
+    pub seed: u64,
+    pub controller: String,
+    /// Settings the run was made with, by name.
+    pub config: BTreeMap<String, String>,
+}
+
+impl Header {
+    /// A header with the current `Settings.toml` as its config.
+    pub fn new(seed: u64, controller: impl Into<String>) -> Self {
+        let config = cfg()
+            .collect()
+            .unwrap()
+            .into_iter()
+            .map(|(key, value)| (key, value.to_string()))
+            .collect();
+        Header {
+            seed,
+            controller: controller.into(),
+            config,
+        }
+    }
+
+    /// A header naming the controller by its type.
+    pub fn for_controller<C>(seed: u64) -> Self {
+        Header::new(seed, std::any::type_name::<C>())
+    }
+
+    /// Record a setting that isn't in `Settings.toml`, e.g. a builder parameter.
+    pub fn with_config(mut self, key: impl Into<String>, value: impl ToString) -> Self {
+        self.config.insert(key.into(), value.to_string());
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Recording {
+    pub header: Header,
+    pub trajectory: Trajectory,
+}
+
+static COLUMNS: [&str; 14] = [
+    "num_crashes_local",
+    "num_cars_throughput",
+    "num_pedestrian_incidents_local",
+    "max_emergency_wait",
+    "green_lights",
+    "queue_length_n",
+    "queue_length_s",
+    "queue_length_e",
+    "queue_length_w",
+    "num_vehicles",
+    "total_wait",
+    "max_wait",
+    "num_spawned_local",
+    "crash_pairs",
+];
+
+impl Recording {
+    pub fn new(header: Header, trajectory: Trajectory) -> Self {
+        Recording { header, trajectory }
+    }
+
+    /// The header as `# key=value` comment lines, then a row of column names, then one row per
+    /// entry. Green lights are written like `N:Through N:Right` and crash pairs like `2-3 4-7`.
+    pub fn write_csv(&self, mut writer: impl Write) -> io::Result<()> {
+        writeln!(writer, "# seed={}", self.header.seed)?;
+        writeln!(writer, "# controller={}", self.header.controller)?;
+        for (key, value) in self.header.config.iter() {
+            writeln!(writer, "# config.{}={}", key, value)?;
+        }
+        writeln!(writer, "{}", COLUMNS.join(","))?;
+        for entry in self.trajectory.iter() {
+            let green_lights: Vec<String> = entry
+                .green_lights()
+                .iter()
+                .map(|(light, movement)| format!("{:?}:{:?}", light, movement))
+                .collect();
+            let crash_pairs: Vec<String> = entry
+                .crash_pairs()
+                .iter()
+                .map(|(a, b)| format!("{}-{}", a, b))
+                .collect();
+            let queue_lengths = Light::all().map(|light| entry.queue_length(&light));
+            writeln!(
+                writer,
+                "{},{},{},{},{},{},{},{},{},{},{},{},{},{}",
+                entry.num_crashes_local(),
+                entry.num_cars_throughput(),
+                entry.num_pedestrian_incidents_local(),
+                entry.max_emergency_wait(),
+                green_lights.join(" "),
+                queue_lengths[0],
+                queue_lengths[1],
+                queue_lengths[2],
+                queue_lengths[3],
+                entry.num_vehicles(),
+                entry.total_wait(),
+                entry.max_wait(),
+                entry.num_spawned_local(),
+                crash_pairs.join(" ")
+            )?;
+        }
+        Ok(())
+    }
+
+    pub fn read_csv(reader: impl BufRead) -> io::Result<Self> {
+        let mut seed = None;
+        let mut controller = None;
+        let mut config = BTreeMap::new();
+        let mut trajectory = Vec::new();
+        let mut seen_columns = false;
+        for line in reader.lines() {
+            let line = line?;
+            if let Some(comment) = line.strip_prefix("# ") {
+                let (key, value) = comment
+                    .split_once('=')
+                    .ok_or_else(|| invalid(format!("header line without '=': {}", line)))?;
+                match key {
+                    "seed" => seed = Some(parse(value)?),
+                    "controller" => controller = Some(value.to_string()),
+                    _ => {
+                        let key = key
+                            .strip_prefix("config.")
+                            .ok_or_else(|| invalid(format!("unknown header line: {}", line)))?;
+                        config.insert(key.to_string(), value.to_string());
+                    }
+                }
+            } else if !seen_columns {
+                if line != COLUMNS.join(",") {
+                    return Err(invalid(format!("unexpected columns: {}", line)));
+                }
+                seen_columns = true;
+            } else if !line.is_empty() {
+                trajectory.push(parse_row(&line)?);
+            }
+        }
+        let header = Header {
+            seed: seed.ok_or_else(|| invalid("no seed in header".to_string()))?,
+            controller: controller.ok_or_else(|| invalid("no controller in header".to_string()))?,
+            config,
+        };
+        Ok(Recording::new(header, trajectory))
+    }
+
+    /// The header as a JSON object on the first line, then one entry per line.
+    pub fn write_jsonl(&self, mut writer: impl Write) -> io::Result<()> {
+        serde_json::to_writer(&mut writer, &self.header)?;
+        writeln!(writer)?;
+        for entry in self.trajectory.iter() {
+            serde_json::to_writer(&mut writer, entry)?;
+            writeln!(writer)?;
+        }
+        Ok(())
+    }
+
+    pub fn read_jsonl(reader: impl BufRead) -> io::Result<Self> {
+        let mut lines = reader
+            .lines()
+            .filter(|line| line.as_ref().map_or(true, |line| !line.trim().is_empty()));
+        let header = serde_json::from_str(
+            &lines
+                .next()
+                .ok_or_else(|| invalid("no header line".to_string()))??,
+        )?;
+        let trajectory = lines
+            .map(|line| Ok(serde_json::from_str(&line?)?))
+            .collect::<io::Result<Trajectory>>()?;
+        Ok(Recording::new(header, trajectory))
+    }
+}
+
+fn invalid(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+fn parse<T: std::str::FromStr>(field: &str) -> io::Result<T> {
+    field
+        .parse()
+        .map_err(|_| invalid(format!("can't parse field: {}", field)))
+}
+
+fn parse_row(line: &str) -> io::Result<TrajectoryEntry> {
+    let fields: Vec<&str> = line.split(',').collect();
+    if fields.len() != COLUMNS.len() {
+        return Err(invalid(format!(
+            "expected {} fields: {}",
+            COLUMNS.len(),
+            line
+        )));
+    }
+    let green_lights = fields[4]
+        .split_whitespace()
+        .map(|green| {
+            let (light, movement) = green
+                .split_once(':')
+                .ok_or_else(|| invalid(format!("can't parse green light: {}", green)))?;
+            Ok((parse_light(light)?, parse_movement(movement)?))
+        })
+        .collect::<io::Result<Vec<(Light, Movement)>>>()?;
+    let crash_pairs = fields[13]
+        .split_whitespace()
+        .map(|pair| {
+            let (a, b) = pair
+                .split_once('-')
+                .ok_or_else(|| invalid(format!("can't parse crash pair: {}", pair)))?;
+            Ok((parse(a)?, parse(b)?))
+        })
+        .collect::<io::Result<Vec<(CarId, CarId)>>>()?;
+    let entry = TrajectoryEntry::new(parse(fields[0])?, parse(fields[1])?)
+        .with_pedestrian_incidents(parse(fields[2])?)
+        .with_max_emergency_wait(parse(fields[3])?)
+        .with_green_lights(green_lights)
+        .with_wait_totals(parse(fields[9])?, parse(fields[10])?, parse(fields[11])?)
+        .with_spawned(parse(fields[12])?)
+        .with_crash_pairs(crash_pairs);
+    Light::all()
+        .into_iter()
+        .zip(&fields[5..9])
+        .try_fold(entry, |entry, (light, field)| {
+            Ok(entry.with_queue_length(&light, parse(field)?))
+        })
+}
+
+fn parse_light(name: &str) -> io::Result<Light> {
+    Light::all()
+        .into_iter()
+        .find(|light| format!("{:?}", light) == name)
+        .ok_or_else(|| invalid(format!("no such light: {}", name)))
+}
+
+fn parse_movement(name: &str) -> io::Result<Movement> {
+    Movement::all()
+        .into_iter()
+        .find(|movement| format!("{:?}", movement) == name)
+        .ok_or_else(|| invalid(format!("no such movement: {}", name)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::prng::Rng;
+    use crate::gatekeeper::model::Model;
+    use crate::traffic::light::Phase;
+    use crate::traffic::simulation::{Random, SimulationBuilder};
+    use rand::SeedableRng;
+
+    fn recording() -> Recording {
+        let mut simulation = SimulationBuilder::<Random>::new().with_max_steps(6).build();
+        let mut trajectory = simulation.rollout(Phase::EastWest, &mut Rng::seed_from_u64(3));
+        trajectory.push(
+            TrajectoryEntry::new(2, 0)
+                .with_green_lights(vec![(Light::E, Movement::Left), (Light::W, Movement::Left)])
+                .with_waits(&[1, 4])
+                .with_crash_pairs(vec![(2, 3), (4, 7)])
+                .with_queue_length(&Light::S, 5),
+        );
+        Recording::new(
+            Header::for_controller::<Random>(3).with_config("max_steps", 6),
+            trajectory,
+        )
+    }
+
+    #[test]
+    fn csv_round_trips() {
+        let recording = recording();
+        let mut buffer = Vec::new();
+        recording.write_csv(&mut buffer).unwrap();
+        assert_eq!(Recording::read_csv(buffer.as_slice()).unwrap(), recording);
+    }
+
+    #[test]
+    fn jsonl_round_trips() {
+        let recording = recording();
+        let mut buffer = Vec::new();
+        recording.write_jsonl(&mut buffer).unwrap();
+        assert_eq!(Recording::read_jsonl(buffer.as_slice()).unwrap(), recording);
+        let text = String::from_utf8(buffer).unwrap();
+        assert_eq!(text.lines().count(), recording.trajectory.len() + 1);
+    }
+
+    #[test]
+    fn header_records_seed_config_and_controller() {
+        let header = recording().header;
+        assert_eq!(header.seed, 3);
+        assert!(header.controller.ends_with("Random"));
+        assert_eq!(header.config["light_coord"], "4");
+        assert_eq!(header.config["max_steps"], "6");
+    }
+}
@@ -0,0 +1,377 @@
+//! The signal heads at the intersection. Controllers don't set lights directly, they request a
+//! phase, and the signal gets there through yellow and all-red clearance once the current green
+//! has been shown for its minimum time. All durations are in drive steps.
+use crate::traffic::geometry::Lane;
+use crate::traffic::light::{CurrentlyGreen, Light, Phase};
+use crate::traffic::movement::Movement;
+use serde::{Deserialize, Serialize};
+
+static MIN_GREEN: u32 = 2;
+static YELLOW: u32 = 1;
+/// No all-red clearance by default, so a phase change can put conflicting cars in the
+/// intersection at once. That is the risk the gatekeeper is there to check.
+static ALL_RED: u32 = 0;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SignalState {
+    Green,
+    /// Cars at the stop line still cross on yellow: in this model none of them is far enough back
+    /// to stop in time.
+    Yellow,
+    Red,
+}
+
+/// What the pedestrian signal across one leg of the intersection shows.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PedestrianState {
+    Walk,
+    /// Pedestrians already on the crosswalk carry on, nobody new steps off the kerb.
+    DontWalk,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+enum Stage {
+    Green(CurrentlyGreen),
+    Yellow(CurrentlyGreen),
+    AllRed,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct Signal {
+    stage: Stage,
+    steps_in_stage: u32,
+    requested: Option<Phase>,
+    min_green: u32,
+    yellow: u32,
+    all_red: u32,
+}
+
+impl Default for Signal {
+    /// Every light red, with the default timings.
+    fn default() -> Self {
+        Signal {
+            stage: Stage::Green(CurrentlyGreen::default()),
+            steps_in_stage: 0,
+            requested: None,
+            min_green: MIN_GREEN,
+            yellow: YELLOW,
+            all_red: ALL_RED,
+        }
+    }
+}
+
+impl Signal {
+    pub fn min_green(&self) -> u32 {
+        self.min_green
+    }
+    pub fn yellow(&self) -> u32 {
+        self.yellow
+    }
+    pub fn all_red(&self) -> u32 {
+        self.all_red
+    }
+    pub(crate) fn set_min_green(&mut self, min_green: u32) {
+        self.min_green = min_green;
+    }
+    pub(crate) fn set_yellow(&mut self, yellow: u32) {
+        self.yellow = yellow;
+    }
+    pub(crate) fn set_all_red(&mut self, all_red: u32) {
+        self.all_red = all_red;
+    }
+
+    /// Turn every movement from `light` green on the spot, skipping the state machine. Only for
+    /// setting up scenarios.
+    pub fn force_green(&mut self, light: Light) {
+        let movements = Movement::all()
+            .into_iter()
+            .map(|movement| (light.clone(), movement));
+        match &mut self.stage {
+            Stage::Green(green) => green.extend(movements),
+            _ => {
+                self.stage = Stage::Green(movements.collect());
+                self.steps_in_stage = 0;
+            }
+        }
+    }
+
+    /// Turn every movement from `light` red on the spot, skipping the state machine. Only for
+    /// setting up scenarios.
+    pub fn force_red(&mut self, light: &Light) {
+        if let Stage::Green(green) | Stage::Yellow(green) = &mut self.stage {
+            green.retain(|(l, _)| l != light);
+        }
+    }
+
+    pub fn state_of(&self, light: &Light, movement: Movement) -> SignalState {
+        let movement = (light.clone(), movement);
+        match &self.stage {
+            Stage::Green(green) if green.contains(&movement) => SignalState::Green,
+            Stage::Yellow(green) if green.contains(&movement) => SignalState::Yellow,
+            _ => SignalState::Red,
+        }
+    }
+
+    /// The most permissive state shown to any movement from `light`.
+    pub fn state(&self, light: &Light) -> SignalState {
+        self.state_of_movements(light, &Movement::all())
+    }
+
+    /// What the signal head over `lane` shows: the most permissive state of its signal group.
+    pub fn state_of_lane(&self, light: &Light, lane: &Lane) -> SignalState {
+        self.state_of_movements(light, lane.movements())
+    }
+
+    fn state_of_movements(&self, light: &Light, movements: &[Movement]) -> SignalState {
+        let states: Vec<SignalState> = movements
+            .iter()
+            .map(|movement| self.state_of(light, *movement))
+            .collect();
+        if states.contains(&SignalState::Green) {
+            SignalState::Green
+        } else if states.contains(&SignalState::Yellow) {
+            SignalState::Yellow
+        } else {
+            SignalState::Red
+        }
+    }
+
+    /// Walk is shown across a leg while traffic alongside the crosswalk has a green and traffic
+    /// on the leg itself doesn't. Turning cars still cross it, as at a real intersection, but
+    /// yield to pedestrians on it.
+    pub fn pedestrian_state(&self, leg: &Light) -> PedestrianState {
+        let green = self.green_lights();
+        let alongside = green.iter().any(|(light, movement)| {
+            light != leg && light != &leg.opposite() && *movement == Movement::Through
+        });
+        let on_leg = green.iter().any(|(light, _)| light == leg);
+        if alongside && !on_leg {
+            PedestrianState::Walk
+        } else {
+            PedestrianState::DontWalk
+        }
+    }
+
+    pub fn green_lights(&self) -> CurrentlyGreen {
+        match &self.stage {
+            Stage::Green(lights) => lights.clone(),
+            _ => CurrentlyGreen::default(),
+        }
+    }
+
+    /// The movements whose cars may cross the stop line: green or yellow.
+    pub fn proceeding(&self) -> CurrentlyGreen {
+        match &self.stage {
+            Stage::Green(lights) | Stage::Yellow(lights) => lights.clone(),
+            Stage::AllRed => CurrentlyGreen::default(),
+        }
+    }
+
+    /// The phase that is green, if the green lights are exactly one phase, or `AllRed` when the
+    /// signal rests with nothing green. None while clearing on yellow or all-red.
+    pub fn phase(&self) -> Option<Phase> {
+        match &self.stage {
+            Stage::Green(green_lights) => std::iter::once(Phase::AllRed)
+                .chain(Phase::all())
+                .find(|phase| &phase.movements() == green_lights),
+            _ => None,
+        }
+    }
+
+    /// The phase the signal is changing to, if it is changing.
+    pub fn requested(&self) -> Option<Phase> {
+        self.requested
+    }
+
+    pub fn steps_in_stage(&self) -> u32 {
+        self.steps_in_stage
+    }
+
+    /// Ask for `phase` to be served. Requesting the phase that is already green cancels any
+    /// change that hasn't reached yellow yet.
+    pub fn request(&mut self, phase: Phase) {
+        if let Stage::Green(_) = self.stage {
+            self.requested = (self.phase() != Some(phase)).then_some(phase);
+        } else {
+            self.requested = Some(phase);
+        }
+        self.settle();
+    }
+
+    /// One drive step has passed.
+    pub(crate) fn tick(&mut self) {
+        self.steps_in_stage += 1;
+        self.settle();
+    }
+
+    /// Move through every stage whose time is up.
+    fn settle(&mut self) {
+        loop {
+            let next = match (&self.stage, self.requested) {
+                // Nothing is green, so there is nothing to clear.
+                (Stage::Green(lights), Some(phase)) if lights.is_empty() => {
+                    self.requested = None;
+                    Stage::Green(phase.movements())
+                }
+                (Stage::Green(lights), Some(_)) if self.steps_in_stage >= self.min_green => {
+                    Stage::Yellow(lights.clone())
+                }
+                (Stage::Yellow(_), _) if self.steps_in_stage >= self.yellow => Stage::AllRed,
+                (Stage::AllRed, Some(phase)) if self.steps_in_stage >= self.all_red => {
+                    self.requested = None;
+                    Stage::Green(phase.movements())
+                }
+                _ => return,
+            };
+            self.stage = next;
+            self.steps_in_stage = 0;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn signal(min_green: u32, yellow: u32, all_red: u32) -> Signal {
+        let mut signal = Signal::default();
+        signal.set_min_green(min_green);
+        signal.set_yellow(yellow);
+        signal.set_all_red(all_red);
+        signal.request(Phase::NorthSouth);
+        signal
+    }
+
+    fn states(signal: &mut Signal, n: usize) -> Vec<(SignalState, SignalState)> {
+        (0..n)
+            .map(|_| {
+                let states = (signal.state(&Light::N), signal.state(&Light::E));
+                signal.tick();
+                states
+            })
+            .collect()
+    }
+
+    #[test]
+    fn first_request_goes_straight_to_green() {
+        let signal = signal(2, 1, 1);
+        assert_eq!(signal.phase(), Some(Phase::NorthSouth));
+        assert_eq!(signal.requested(), None);
+    }
+
+    #[test]
+    fn phase_change_waits_for_min_green_then_clears() {
+        use SignalState::*;
+        let mut signal = signal(2, 1, 1);
+        signal.request(Phase::EastWest);
+        assert_eq!(
+            states(&mut signal, 6),
+            vec![
+                (Green, Red),
+                (Green, Red),
+                (Yellow, Red),
+                (Red, Red),
+                (Red, Green),
+                (Red, Green),
+            ]
+        );
+        assert_eq!(signal.phase(), Some(Phase::EastWest));
+    }
+
+    #[test]
+    fn zero_clearance_switches_after_yellow() {
+        use SignalState::*;
+        let mut signal = signal(0, 1, 0);
+        signal.request(Phase::EastWest);
+        assert_eq!(
+            states(&mut signal, 3),
+            vec![(Yellow, Red), (Red, Green), (Red, Green)]
+        );
+    }
+
+    #[test]
+    fn requesting_current_phase_cancels_change() {
+        let mut signal = signal(4, 1, 1);
+        signal.request(Phase::EastWest);
+        signal.tick();
+        signal.request(Phase::NorthSouth);
+        for _ in 0..8 {
+            signal.tick();
+        }
+        assert_eq!(signal.phase(), Some(Phase::NorthSouth));
+    }
+
+    #[test]
+    fn all_red_phase_clears_then_holds_everything() {
+        use SignalState::*;
+        let mut signal = signal(0, 1, 1);
+        signal.request(Phase::AllRed);
+        assert_eq!(
+            states(&mut signal, 4),
+            vec![(Yellow, Red), (Red, Red), (Red, Red), (Red, Red)]
+        );
+        assert_eq!(signal.phase(), Some(Phase::AllRed));
+        assert!(signal.proceeding().is_empty());
+    }
+
+    #[test]
+    fn yellow_lets_cars_proceed() {
+        let mut signal = signal(0, 2, 0);
+        signal.request(Phase::EastWest);
+        assert_eq!(signal.state(&Light::S), SignalState::Yellow);
+        assert!(signal.proceeding().contains(&(Light::S, Movement::Through)));
+        assert!(signal.green_lights().is_empty());
+    }
+
+    #[test]
+    fn protected_left_phase_only_greens_left_turns() {
+        let mut signal = signal(0, 0, 0);
+        signal.request(Phase::NorthSouthLeft);
+        assert_eq!(signal.phase(), Some(Phase::NorthSouthLeft));
+        assert_eq!(
+            signal.state_of(&Light::N, Movement::Left),
+            SignalState::Green
+        );
+        assert_eq!(
+            signal.state_of(&Light::N, Movement::Through),
+            SignalState::Red
+        );
+        assert_eq!(signal.state(&Light::S), SignalState::Green);
+        assert_eq!(signal.state(&Light::E), SignalState::Red);
+    }
+
+    #[test]
+    fn dedicated_turn_lane_follows_its_own_group() {
+        let mut signal = signal(0, 0, 0);
+        let left = Lane::new(vec![Movement::Left]);
+        let ahead = Lane::new(vec![Movement::Through, Movement::Right]);
+        signal.request(Phase::NorthSouthLeft);
+        assert_eq!(signal.state_of_lane(&Light::N, &left), SignalState::Green);
+        assert_eq!(signal.state_of_lane(&Light::N, &ahead), SignalState::Red);
+        signal.request(Phase::NorthSouth);
+        assert_eq!(signal.state_of_lane(&Light::N, &ahead), SignalState::Green);
+    }
+
+    #[test]
+    fn walk_is_shown_across_the_legs_alongside_the_green() {
+        let mut signal = signal(0, 1, 0);
+        assert_eq!(signal.pedestrian_state(&Light::E), PedestrianState::Walk);
+        assert_eq!(
+            signal.pedestrian_state(&Light::N),
+            PedestrianState::DontWalk
+        );
+        signal.request(Phase::EastWest);
+        assert_eq!(
+            signal.pedestrian_state(&Light::E),
+            PedestrianState::DontWalk
+        );
+        signal.tick();
+        assert_eq!(signal.pedestrian_state(&Light::N), PedestrianState::Walk);
+        signal.request(Phase::EastWestLeft);
+        signal.tick();
+        assert_eq!(
+            signal.pedestrian_state(&Light::N),
+            PedestrianState::DontWalk
+        );
+    }
+}
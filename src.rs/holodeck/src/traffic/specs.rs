@@ -0,0 +1,99 @@
+//! Specs for the intersection, ready to hand to `GatekeeperBuilder::with_spec`.
+use crate::logic::syntax::Prop;
+use crate::traffic::trajectory::{Reading, TrajectoryEntry};
+
+/// Nobody crashes and nobody is hit.
+pub fn safety(trajectory: Vec<TrajectoryEntry>) -> Prop<TrajectoryEntry> {
+    Prop::var(trajectory).always()
+}
+
+/// An emergency vehicle never waits more than `k` steps.
+pub fn emergency_wait_within(k: u32) -> impl Fn(Vec<TrajectoryEntry>) -> Prop<TrajectoryEntry> {
+    move |trajectory| {
+        Prop::var(
+            trajectory
+                .into_iter()
+                .map(|entry| entry.read_as(Reading::EmergencyWaitWithin(k)))
+                .collect(),
+        )
+        .always()
+    }
+}
+
+/// Safety, without holding up emergency vehicles for more than `k` steps to get it.
+pub fn safety_and_emergency_wait_within(
+    k: u32,
+) -> impl Fn(Vec<TrajectoryEntry>) -> Prop<TrajectoryEntry> {
+    let emergency = emergency_wait_within(k);
+    move |trajectory| safety(trajectory.clone()).and(emergency(trajectory))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::cfg;
+    use crate::gatekeeper::{Gatekeeper, GatekeeperBuilder};
+    use crate::logic::types::Atomic;
+    use crate::traffic::car::VehicleClass;
+    use crate::traffic::controllers::{FixedTime, Preempting};
+    use crate::traffic::intersection::IntersectionBuilder;
+    use crate::traffic::light::{Light, Phase};
+    use crate::traffic::movement::Movement;
+    use crate::traffic::simulation::{Simulation, SimulationBuilder};
+
+    fn waiting(wait: u32) -> TrajectoryEntry {
+        TrajectoryEntry::new(0, 1).with_max_emergency_wait(wait)
+    }
+
+    #[test]
+    fn emergency_wait_reading_holds_only_within_k() {
+        let within = |wait| waiting(wait).read_as(Reading::EmergencyWaitWithin(3)).val();
+        assert_eq!(within(3), 1.0);
+        assert_eq!(within(4), 0.0);
+        // Waiting doesn't count against safety.
+        assert_eq!(waiting(4).val(), 1.0);
+    }
+
+    #[test]
+    fn emergency_wait_spec_is_always_over_the_wait_reading() {
+        let trajectory = vec![waiting(1), waiting(5)];
+        let read: Vec<TrajectoryEntry> = trajectory
+            .iter()
+            .map(|entry| entry.clone().read_as(Reading::EmergencyWaitWithin(3)))
+            .collect();
+        assert_eq!(
+            emergency_wait_within(3)(trajectory.clone()),
+            Prop::var(read.clone()).always()
+        );
+        assert_eq!(
+            safety_and_emergency_wait_within(3)(trajectory.clone()),
+            Prop::var(trajectory).always().and(Prop::var(read).always())
+        );
+    }
+
+    #[test]
+    fn gatekeeper_lets_preemption_through_for_an_emergency_vehicle() {
+        type Controller = Preempting<FixedTime>;
+        let north_south_only = || Preempting::new(FixedTime::new(vec![(Phase::NorthSouth, 1)]));
+        let mut intersection = IntersectionBuilder::new().build();
+        intersection.request_phase(Phase::NorthSouth);
+        intersection.spawn_vehicle_in_lane(Light::E, 0, Movement::Through, VehicleClass::Emergency);
+        let simulation = SimulationBuilder::new()
+            .with_intersection(intersection)
+            .with_controller(north_south_only())
+            .with_drive_steps_per_lightswitch(4)
+            .with_max_steps(cfg().get("max_timestamp").unwrap())
+            .build();
+        let mut gatekeeper: Gatekeeper<Controller, Simulation<Controller>> =
+            GatekeeperBuilder::new(simulation.clone(), simulation)
+                .with_controller(north_south_only())
+                .with_spec(safety_and_emergency_wait_within(1))
+                .build();
+        let episode = gatekeeper.run_episode(1);
+        let decision = &episode.decisions()[0];
+        assert_eq!(decision.proposed(), &Phase::EastWest);
+        assert_eq!(decision.applied(), &Phase::EastWest);
+        assert_eq!(decision.num_rejections(), 0);
+        assert_eq!(episode.trajectory()[0].max_emergency_wait(), 0);
+    }
+}
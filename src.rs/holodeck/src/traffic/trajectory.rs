@@ -1,18 +1,57 @@
+use crate::gatekeeper::Episode;
 use crate::logic::types::Atomic;
+use crate::traffic::car::CarId;
+use crate::traffic::light::Light;
+use crate::traffic::movement::Movement;
+use serde::{Deserialize, Serialize};
 use std::fmt::{Debug, Display, Formatter, Result};
 
-#[derive(Debug, Clone, PartialEq, Eq, std::hash::Hash, Copy)]
+#[derive(Debug, Clone, PartialEq, Eq, std::hash::Hash, Serialize, Deserialize)]
 pub struct TrajectoryEntry {
     num_crashes_local: u32,
     num_cars_throughput: u32,
+    num_pedestrian_incidents_local: u32,
+    max_emergency_wait: u32,
+    /// What the intersection looked like at the end of the period.
+    green_lights: Vec<(Light, Movement)>,
+    /// Indexed like `Light::all`.
+    queue_lengths: [u32; 4],
+    num_vehicles: u32,
+    total_wait: u32,
+    max_wait: u32,
+    num_spawned_local: u32,
+    /// Lower id first, in the order the crashes happened.
+    crash_pairs: Vec<(CarId, CarId)>,
+    /// How a spec reads the entry, which isn't part of what happened.
+    #[serde(skip)]
+    reading: Reading,
 }
 
+/// Which property of an entry `Atomic::val` reports, so specs can be about more than crashes.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, std::hash::Hash)]
+pub enum Reading {
+    /// 1 with no incidents, falling towards 0 the more severe they were.
+    #[default]
+    Safety,
+    /// 1 if no emergency vehicle had waited more than this many steps, 0 otherwise.
+    EmergencyWaitWithin(u32),
+}
+
+/// How many crashes between cars one car hitting a pedestrian counts as.
+static PEDESTRIAN_SEVERITY: f64 = 4.0;
+
 impl Display for TrajectoryEntry {
     fn fmt(&self, f: &mut Formatter<'_>) -> Result {
         write!(
             f,
-            "TrajectoryEntry(num_crashes_local={}, num_cars_throughput={})",
-            self.num_crashes_local, self.num_cars_throughput
+            "TrajectoryEntry(num_crashes_local={}, num_cars_throughput={}, num_pedestrian_incidents_local={}, max_emergency_wait={}, num_vehicles={}, max_wait={}, num_spawned_local={})",
+            self.num_crashes_local,
+            self.num_cars_throughput,
+            self.num_pedestrian_incidents_local,
+            self.max_emergency_wait,
+            self.num_vehicles,
+            self.max_wait,
+            self.num_spawned_local
         )
     }
 }
@@ -22,21 +61,200 @@ impl TrajectoryEntry {
         Self {
             num_crashes_local,
             num_cars_throughput,
+            num_pedestrian_incidents_local: 0,
+            max_emergency_wait: 0,
+            green_lights: Vec::new(),
+            queue_lengths: [0; 4],
+            num_vehicles: 0,
+            total_wait: 0,
+            max_wait: 0,
+            num_spawned_local: 0,
+            crash_pairs: Vec::new(),
+            reading: Reading::default(),
         }
     }
+    pub fn with_pedestrian_incidents(mut self, num_pedestrian_incidents_local: u32) -> Self {
+        self.num_pedestrian_incidents_local = num_pedestrian_incidents_local;
+        self
+    }
     pub fn num_crashes_local(&self) -> u32 {
         self.num_crashes_local
     }
+    pub fn with_max_emergency_wait(mut self, max_emergency_wait: u32) -> Self {
+        self.max_emergency_wait = max_emergency_wait;
+        self
+    }
+    /// The movements showing green, in the order of `Light::all` then `Movement::all`.
+    pub fn with_green_lights(mut self, green_lights: Vec<(Light, Movement)>) -> Self {
+        self.green_lights = green_lights;
+        self
+    }
+    pub fn with_queue_length(mut self, light: &Light, queue_length: u32) -> Self {
+        self.queue_lengths[index_of(light)] = queue_length;
+        self
+    }
+    /// The vehicles on the road, given how many steps each has spent standing still.
+    pub fn with_waits(mut self, waits: &[u32]) -> Self {
+        self.num_vehicles = waits.len() as u32;
+        self.total_wait = waits.iter().sum();
+        self.max_wait = waits.iter().copied().max().unwrap_or(0);
+        self
+    }
+    /// What `with_waits` would have recorded, for when only the totals are known.
+    pub(crate) fn with_wait_totals(
+        mut self,
+        num_vehicles: u32,
+        total_wait: u32,
+        max_wait: u32,
+    ) -> Self {
+        self.num_vehicles = num_vehicles;
+        self.total_wait = total_wait;
+        self.max_wait = max_wait;
+        self
+    }
+    pub fn with_spawned(mut self, num_spawned_local: u32) -> Self {
+        self.num_spawned_local = num_spawned_local;
+        self
+    }
+    pub fn with_crash_pairs(mut self, crash_pairs: Vec<(CarId, CarId)>) -> Self {
+        self.crash_pairs = crash_pairs;
+        self
+    }
+    /// The same entry, valued by `reading` when a spec refers to it.
+    pub fn read_as(mut self, reading: Reading) -> Self {
+        self.reading = reading;
+        self
+    }
     pub fn num_cars_throughput(&self) -> u32 {
         self.num_cars_throughput
     }
+    /// Cars that hit a pedestrian during the period. Not included in `num_crashes_local`.
+    pub fn num_pedestrian_incidents_local(&self) -> u32 {
+        self.num_pedestrian_incidents_local
+    }
+    /// The longest any emergency vehicle on the road during the period had spent standing still.
+    pub fn max_emergency_wait(&self) -> u32 {
+        self.max_emergency_wait
+    }
+    pub fn green_lights(&self) -> &[(Light, Movement)] {
+        &self.green_lights
+    }
+    /// Cars upstream of the stop line on `light` at the end of the period.
+    pub fn queue_length(&self, light: &Light) -> u32 {
+        self.queue_lengths[index_of(light)]
+    }
+    /// Vehicles on the road at the end of the period.
+    pub fn num_vehicles(&self) -> u32 {
+        self.num_vehicles
+    }
+    /// The average number of steps the vehicles on the road have spent standing still.
+    pub fn mean_wait(&self) -> f64 {
+        if self.num_vehicles == 0 {
+            0.0
+        } else {
+            self.total_wait as f64 / self.num_vehicles as f64
+        }
+    }
+    /// Steps all the vehicles on the road have spent standing still, between them.
+    pub fn total_wait(&self) -> u32 {
+        self.total_wait
+    }
+    pub fn max_wait(&self) -> u32 {
+        self.max_wait
+    }
+    /// Vehicles that arrived during the period.
+    pub fn num_spawned_local(&self) -> u32 {
+        self.num_spawned_local
+    }
+    /// The cars in each crash during the period. There is one pair per crash counted in
+    /// `num_crashes_local`.
+    pub fn crash_pairs(&self) -> &[(CarId, CarId)] {
+        &self.crash_pairs
+    }
+    /// Crashes and pedestrian incidents, the latter weighted by how much more severe they are.
+    pub fn severity(&self) -> f64 {
+        self.num_crashes_local as f64
+            + PEDESTRIAN_SEVERITY * self.num_pedestrian_incidents_local as f64
+    }
+}
+
+fn index_of(light: &Light) -> usize {
+    Light::all()
+        .iter()
+        .position(|l| l == light)
+        .expect("every light is in Light::all")
 }
 
 pub type Trajectory = Vec<TrajectoryEntry>;
 
+/// Cars through the intersection over the trajectory, for ranking actions by performance.
+pub fn throughput(trajectory: &[TrajectoryEntry]) -> f64 {
+    trajectory
+        .iter()
+        .map(|entry| entry.num_cars_throughput() as f64)
+        .sum()
+}
+
+/// Reward for one decision period: cars through the intersection, minus `crash_penalty` per crash
+/// and more per pedestrian hit.
+pub fn reward(crash_penalty: f64) -> impl Fn(&TrajectoryEntry) -> f64 {
+    move |entry| entry.num_cars_throughput() as f64 - crash_penalty * entry.severity()
+}
+
 impl Atomic for TrajectoryEntry {
     fn val(&self) -> f64 {
         // println!("num_crashes_local: {}", self.num_crashes_local);
-        1.0 / (1.0 + self.num_crashes_local as f64)
+        match self.reading {
+            Reading::Safety => 1.0 / (1.0 + self.severity()),
+            Reading::EmergencyWaitWithin(k) => (self.max_emergency_wait <= k) as u32 as f64,
+        }
+    }
+}
+
+impl<A> Episode<A, TrajectoryEntry> {
+    pub fn num_crashes(&self) -> u32 {
+        self.trajectory()
+            .iter()
+            .map(|entry| entry.num_crashes_local())
+            .sum()
+    }
+    pub fn total_throughput(&self) -> u32 {
+        self.trajectory()
+            .iter()
+            .map(|entry| entry.num_cars_throughput())
+            .sum()
+    }
+    pub fn num_pedestrian_incidents(&self) -> u32 {
+        self.trajectory()
+            .iter()
+            .map(|entry| entry.num_pedestrian_incidents_local())
+            .sum()
+    }
+    pub fn max_emergency_wait(&self) -> u32 {
+        self.trajectory()
+            .iter()
+            .map(|entry| entry.max_emergency_wait())
+            .max()
+            .unwrap_or(0)
+    }
+    /// Running total of crashes after each decision period.
+    pub fn cumulative_crashes(&self) -> Vec<u32> {
+        self.trajectory()
+            .iter()
+            .scan(0, |total, entry| {
+                *total += entry.num_crashes_local();
+                Some(*total)
+            })
+            .collect()
+    }
+    /// Running total of throughput after each decision period.
+    pub fn cumulative_throughput(&self) -> Vec<u32> {
+        self.trajectory()
+            .iter()
+            .scan(0, |total, entry| {
+                *total += entry.num_cars_throughput();
+                Some(*total)
+            })
+            .collect()
     }
 }
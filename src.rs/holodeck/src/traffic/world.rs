@@ -0,0 +1,366 @@
+//! The world the gatekeeper's certified actions are applied to. It drives the same intersection as
+//! `Simulation`, but its drivers and detectors make mistakes the simulation doesn't model, so
+//! there is a real gap between what was certified and what happens.
+use rand::Rng as _;
+
+use crate::data::prng::Rng;
+use crate::gatekeeper::model::{FiniteActions, Model};
+use crate::traffic::car::{Car, CarId};
+use crate::traffic::intersection::Intersection;
+use crate::traffic::light::Phase;
+use crate::traffic::observation::Observation;
+use crate::traffic::simulation::{LightController, Simulation, SimulationBuilder};
+use crate::traffic::trajectory::{Trajectory, TrajectoryEntry};
+use std::collections::HashSet;
+
+/// How often drivers and detectors get it wrong. Every probability is per car per drive step, and
+/// all of them default to zero.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Disturbances {
+    /// A car at a red stop line drives on anyway.
+    pub red_light_running: f64,
+    /// A car that stood still last step is slow to move off.
+    pub delayed_start: f64,
+    /// A car that could move stops for no reason.
+    pub random_stop: f64,
+    /// A car is missing from what the controller is shown.
+    pub sensor_dropout: f64,
+}
+
+impl Disturbances {
+    /// Whether a car tries to move, given whether the rules let it.
+    fn drives(&self, car: &Car, allowed: bool, rng: &mut Rng) -> bool {
+        if !allowed {
+            return happens(self.red_light_running, rng);
+        }
+        !(car.standing && happens(self.delayed_start, rng) || happens(self.random_stop, rng))
+    }
+}
+
+/// Doesn't draw from `rng` when `probability` is zero, so an undisturbed world makes the same
+/// random choices as the simulation.
+fn happens(probability: f64, rng: &mut Rng) -> bool {
+    probability > 0.0 && rng.gen_bool(probability.min(1.0))
+}
+
+#[derive(Clone)]
+pub struct World<C: LightController> {
+    simulation: Simulation<C>,
+    disturbances: Disturbances,
+    /// Cars the detectors missed at the end of the last drive step.
+    hidden: HashSet<CarId>,
+}
+
+pub struct WorldBuilder<C: LightController> {
+    world: World<C>,
+}
+
+impl<C: LightController> Default for WorldBuilder<C> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<C: LightController> WorldBuilder<C> {
+    pub fn new() -> Self {
+        WorldBuilder {
+            world: World {
+                simulation: SimulationBuilder::new().build(),
+                disturbances: Disturbances::default(),
+                hidden: HashSet::new(),
+            },
+        }
+    }
+
+    /// The intersection, demand, controller and horizon the world runs with.
+    pub fn with_simulation(mut self, simulation: Simulation<C>) -> Self {
+        self.world.simulation = simulation;
+        self
+    }
+
+    pub fn with_disturbances(mut self, disturbances: Disturbances) -> Self {
+        self.world.disturbances = disturbances;
+        self
+    }
+
+    pub fn build(self) -> World<C> {
+        self.world
+    }
+}
+
+impl<C: LightController> World<C> {
+    pub fn intersection(&self) -> &Intersection {
+        self.simulation.intersection()
+    }
+    pub fn disturbances(&self) -> &Disturbances {
+        &self.disturbances
+    }
+    pub fn max_steps(&self) -> u32 {
+        self.simulation.max_steps()
+    }
+
+    fn ask_controller(&mut self, rng: &mut Rng) {
+        let observation = self.observe();
+        let action = self
+            .simulation
+            .controller_mut()
+            .select_action(&observation, rng);
+        self.simulation.intersection_mut().request_phase(action);
+    }
+
+    fn drive_between_lightswitch(&mut self, rng: &mut Rng) {
+        for _ in 0..self.simulation.drive_steps_per_lightswitch() {
+            self.simulation.spawn_step(rng);
+            let disturbances = &self.disturbances;
+            self.simulation
+                .intersection_mut()
+                .advance_with(|car, allowed| disturbances.drives(car, allowed, rng));
+        }
+        self.hidden = self
+            .intersection()
+            .cars
+            .iter()
+            .filter(|_| happens(self.disturbances.sensor_dropout, rng))
+            .map(|car| car.id)
+            .collect();
+    }
+
+    pub fn run(&mut self, rng: &mut Rng) {
+        for _ in 0..self.max_steps() {
+            self.drive_between_lightswitch(rng);
+            self.ask_controller(rng);
+        }
+    }
+}
+
+impl<C: LightController> Model for World<C> {
+    type State = Intersection;
+    type Action = Phase;
+    type Observation = Observation;
+    type Atom = TrajectoryEntry;
+
+    fn snapshot(&self) -> Intersection {
+        self.simulation.snapshot()
+    }
+
+    /// What the detectors picked up, which may be missing some cars.
+    fn observe(&self) -> Observation {
+        let intersection = self.intersection();
+        let seen: Vec<Car> = intersection
+            .cars
+            .iter()
+            .filter(|car| !self.hidden.contains(&car.id))
+            .cloned()
+            .collect();
+        Observation::of_cars(&seen, intersection.geometry(), intersection.signal())
+    }
+
+    fn sync_from(&mut self, snapshot: &Intersection) {
+        self.simulation.sync_from(snapshot);
+        self.hidden.clear();
+    }
+
+    fn channels(entry: &TrajectoryEntry) -> Vec<f64> {
+        Simulation::<C>::channels(entry)
+    }
+
+    fn step(&mut self, action: Phase, rng: &mut Rng) -> TrajectoryEntry {
+        let before = self.simulation.intersection_mut().tally();
+        self.simulation.intersection_mut().request_phase(action);
+        self.drive_between_lightswitch(rng);
+        self.intersection().entry_since(before)
+    }
+
+    fn rollout(&mut self, action: Phase, rng: &mut Rng) -> Trajectory {
+        self.simulation.intersection_mut().request_phase(action);
+        (0..self.max_steps())
+            .map(|_| {
+                let before = self.simulation.intersection_mut().tally();
+                self.drive_between_lightswitch(rng);
+                self.ask_controller(rng);
+                self.intersection().entry_since(before)
+            })
+            .collect()
+    }
+}
+
+impl<C: LightController> FiniteActions for World<C> {
+    fn actions(&self) -> Vec<Phase> {
+        Phase::all().to_vec()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::cfg::cfg;
+    use crate::gatekeeper::fidelity::Fidelity;
+    use crate::gatekeeper::tests::small_simulation;
+    use crate::gatekeeper::{Gatekeeper, GatekeeperBuilder};
+    use crate::logic::syntax::Prop;
+    use crate::traffic::demand::{ApproachDemand, ArrivalProcess, Demand, Profile, Proportions};
+    use crate::traffic::intersection::IntersectionBuilder;
+    use crate::traffic::light::Light;
+    use crate::traffic::movement::Movement;
+    use crate::traffic::simulation::Random;
+    use rand::SeedableRng;
+
+    fn world(disturbances: Disturbances) -> World<Random> {
+        WorldBuilder::new()
+            .with_simulation(small_simulation())
+            .with_disturbances(disturbances)
+            .build()
+    }
+
+    /// A car from the north driven up to its stop line, with every light red.
+    fn queued_world(disturbances: Disturbances) -> World<Random> {
+        let light_coord: u32 = cfg().get("light_coord").unwrap();
+        let mut intersection = IntersectionBuilder::new().build();
+        intersection.spawn_car(Light::N);
+        for _ in 0..light_coord {
+            intersection.advance();
+        }
+        let mut world = world(disturbances);
+        world.sync_from(&intersection);
+        world
+    }
+
+    fn advance(world: &mut World<Random>, rng: &mut Rng) {
+        let disturbances = world.disturbances.clone();
+        world
+            .simulation
+            .intersection_mut()
+            .advance_with(|car, allowed| disturbances.drives(car, allowed, rng));
+    }
+
+    #[test]
+    fn undisturbed_world_follows_the_simulation() {
+        let rollout = |model: &mut dyn FnMut(&mut Rng) -> Trajectory| {
+            let mut prng = Rng::seed_from_u64(7);
+            model(&mut prng)
+        };
+        let mut simulation = small_simulation();
+        let mut world = world(Disturbances::default());
+        assert_eq!(
+            rollout(&mut |prng| simulation.rollout(Phase::NorthSouth, prng)),
+            rollout(&mut |prng| world.rollout(Phase::NorthSouth, prng))
+        );
+        assert_eq!(
+            world.intersection().cars.len(),
+            simulation.intersection().cars.len()
+        );
+    }
+
+    #[test]
+    fn red_light_runner_crosses_on_red() {
+        let light_coord: u32 = cfg().get("light_coord").unwrap();
+        let mut prng = Rng::seed_from_u64(0);
+        let mut world = queued_world(Disturbances {
+            red_light_running: 1.0,
+            ..Disturbances::default()
+        });
+        advance(&mut world, &mut prng);
+        assert_eq!(world.intersection().cars[0].position, light_coord + 1);
+    }
+
+    #[test]
+    fn delayed_start_holds_a_queued_car_on_green() {
+        let light_coord: u32 = cfg().get("light_coord").unwrap();
+        let mut prng = Rng::seed_from_u64(0);
+        let mut world = queued_world(Disturbances {
+            delayed_start: 1.0,
+            ..Disturbances::default()
+        });
+        advance(&mut world, &mut prng);
+        world
+            .simulation
+            .intersection_mut()
+            .request_phase(Phase::NorthSouth);
+        advance(&mut world, &mut prng);
+        assert_eq!(world.intersection().cars[0].position, light_coord);
+    }
+
+    #[test]
+    fn random_stops_keep_cars_where_they_are() {
+        let mut prng = Rng::seed_from_u64(0);
+        let mut world = world(Disturbances {
+            random_stop: 1.0,
+            ..Disturbances::default()
+        });
+        world.simulation.intersection_mut().spawn_car(Light::E);
+        for _ in 0..3 {
+            advance(&mut world, &mut prng);
+        }
+        assert_eq!(world.intersection().cars[0].position, 0);
+    }
+
+    #[test]
+    fn sensor_dropout_hides_cars_from_the_controller() {
+        let mut prng = Rng::seed_from_u64(0);
+        let mut world = queued_world(Disturbances {
+            sensor_dropout: 1.0,
+            ..Disturbances::default()
+        });
+        assert_eq!(world.observe().queue_length(&Light::N), 1);
+        world.drive_between_lightswitch(&mut prng);
+        assert_eq!(world.observe().queue_length(&Light::N), 0);
+        // The car is still there, the detectors just didn't see it.
+        assert!(world.snapshot().observe().queue_length(&Light::N) > 0);
+    }
+
+    #[test]
+    fn red_light_running_world_stops_the_simulation_being_predictive() {
+        // Straight-through traffic with all-red clearance never crashes in simulation.
+        let through = Demand::default().with_approach(
+            Light::N,
+            ApproachDemand::new(ArrivalProcess::Bernoulli, Profile::constant(0.5))
+                .with_turning(Proportions::only(Movement::Through)),
+        );
+        let through =
+            [Light::S, Light::E, Light::W]
+                .into_iter()
+                .fold(through.clone(), |demand, light| {
+                    demand.with_approach(light, through.approach(&Light::N).unwrap().clone())
+                });
+        let simulation = SimulationBuilder::<Random>::new()
+            .with_intersection(IntersectionBuilder::new().with_all_red(1).build())
+            .with_demand(through)
+            .with_max_cars(16)
+            .with_drive_steps_per_lightswitch(8)
+            .with_max_steps(2)
+            .build();
+        let world = WorldBuilder::new()
+            .with_simulation(simulation.clone())
+            .with_disturbances(Disturbances {
+                red_light_running: 1.0,
+                ..Disturbances::default()
+            })
+            .build();
+        let mut gatekeeper: Gatekeeper<Random, Simulation<Random>, World<Random>> =
+            GatekeeperBuilder::new(simulation, world)
+                .with_spec(Prop::var)
+                .with_fidelity(Fidelity::new(0.1, 4))
+                .with_seed(0)
+                .build();
+        assert!(gatekeeper.fidelity().is_predictive());
+        gatekeeper.run_episode(8);
+        assert_eq!(gatekeeper.fidelity().num_verdicts(), 8);
+        assert!(!gatekeeper.fidelity().is_predictive());
+    }
+
+    #[test]
+    fn gatekeeper_certifies_in_simulation_and_acts_in_disturbed_world() {
+        let mut gatekeeper: Gatekeeper<Random, Simulation<Random>, World<Random>> =
+            GatekeeperBuilder::new(
+                small_simulation(),
+                world(Disturbances {
+                    red_light_running: 0.2,
+                    ..Disturbances::default()
+                }),
+            )
+            .with_seed(0)
+            .build();
+        let episode = gatekeeper.run_episode(3);
+        assert_eq!(episode.trajectory().len(), 3);
+    }
+}